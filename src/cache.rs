@@ -0,0 +1,371 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use config::Config;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::output::{OutputLine, OutputStream};
+use crate::script::Script;
+use crate::utils::config::{get_optional_size, get_state_dir};
+use crate::utils::error::Error;
+use crate::utils::files::expand_globs;
+
+/// Limit on the total size of the cache unless `[cache] max_size` is set.
+const DEFAULT_MAX_SIZE: u64 = 1 << 30;
+const ENTRY_FILE: &str = "entry.json";
+const FILES_DIR: &str = "files";
+
+/// Results of earlier runs of scripts, keyed on a hash of everything that
+/// determines what a script produces. Each entry is a directory holding the
+/// script's output files and the lines it printed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cache {
+    pub dir: PathBuf,
+    /// Once the entries take up more than this many bytes, the least recently
+    /// used ones are removed.
+    pub max_size: u64,
+}
+
+/// A line printed by a cached script, replayed when its results are restored.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CachedLine {
+    pub stream: OutputStream,
+    pub line: String,
+}
+
+impl From<&OutputLine> for CachedLine {
+    fn from(output: &OutputLine) -> CachedLine {
+        CachedLine {
+            stream: output.stream,
+            line: output.line.clone(),
+        }
+    }
+}
+
+/// The files of an entry are stored as `files/<index>`, where `index` is the
+/// position of their original path in `files`.
+#[derive(Debug, Serialize, Deserialize)]
+struct CacheEntry {
+    files: Vec<PathBuf>,
+    output: Vec<CachedLine>,
+}
+
+impl Cache {
+    /// Restores the output files stored under `key` and returns the lines the
+    /// script printed, or `None` if nothing is cached under `key`.
+    pub fn restore(&self, key: &str) -> Result<Option<Vec<CachedLine>>, Error> {
+        let entry_dir = self.dir.join(key);
+        let entry_file = entry_dir.join(ENTRY_FILE);
+        let contents = match fs::read_to_string(&entry_file) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(Error::Io(err)),
+        };
+        let entry: CacheEntry = serde_json::from_str(&contents).map_err(|err| {
+            Error::Unspecified(format!(
+                "invalid cache entry {}: {}",
+                entry_file.display(),
+                err
+            ))
+        })?;
+
+        for (index, file) in entry.files.iter().enumerate() {
+            if let Some(parent) = file.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::copy(entry_dir.join(FILES_DIR).join(index.to_string()), file)?;
+        }
+
+        // Mark the entry as recently used, so that it is evicted last.
+        fs::File::options()
+            .write(true)
+            .open(&entry_file)?
+            .set_modified(SystemTime::now())?;
+        Ok(Some(entry.output))
+    }
+
+    /// Stores the current contents of `script`'s outputs and the lines it
+    /// printed under `key`, then evicts old entries if the cache is too big.
+    pub fn store(&self, key: &str, script: &Script, output: &[CachedLine]) -> Result<(), Error> {
+        // Entries are written next to their final location and renamed, so
+        // an interrupted run never leaves an incomplete entry behind.
+        let entry_dir = self.dir.join(key);
+        let tmp_dir = self.dir.join(format!("{}.tmp", key));
+        remove_dir_if_exists(&tmp_dir)?;
+        fs::create_dir_all(tmp_dir.join(FILES_DIR))?;
+
+        let mut files = Vec::new();
+        for path in expand_globs(&script.outputs)? {
+            collect_files(&path, &mut files)?;
+        }
+        files.sort();
+        files.dedup();
+        for (index, file) in files.iter().enumerate() {
+            fs::copy(file, tmp_dir.join(FILES_DIR).join(index.to_string()))?;
+        }
+
+        let entry = CacheEntry {
+            files,
+            output: output.to_vec(),
+        };
+        let contents =
+            serde_json::to_string(&entry).map_err(|err| Error::Unspecified(err.to_string()))?;
+        fs::write(tmp_dir.join(ENTRY_FILE), contents)?;
+        remove_dir_if_exists(&entry_dir)?;
+        fs::rename(&tmp_dir, &entry_dir)?;
+
+        self.evict()
+    }
+
+    /// Removes every entry. Returns the number of bytes freed.
+    pub fn clean(&self) -> Result<u64, Error> {
+        let size = dir_size(&self.dir)?;
+        remove_dir_if_exists(&self.dir)?;
+        Ok(size)
+    }
+
+    /// Removes the least recently used entries until the cache fits in
+    /// `max_size`. Entries still being written by other scripts, and entries
+    /// that disappear while this runs, are left alone.
+    fn evict(&self) -> Result<(), Error> {
+        let mut entries = Vec::new();
+        for dir_entry in fs::read_dir(&self.dir)? {
+            let path = dir_entry?.path();
+            if path.extension().is_some_and(|extension| extension == "tmp") {
+                continue;
+            }
+            let used = match fs::metadata(path.join(ENTRY_FILE)).or_else(|_| fs::metadata(&path)) {
+                Ok(metadata) => metadata.modified()?,
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(err) => return Err(Error::Io(err)),
+            };
+            entries.push((used, dir_size(&path)?, path));
+        }
+        entries.sort();
+
+        let mut total = entries.iter().map(|(_, size, _)| size).sum::<u64>();
+        for (_, size, path) in entries {
+            if total <= self.max_size {
+                break;
+            }
+            debug!("Evicting cache entry {}", path.display());
+            remove_dir_if_exists(&path)?;
+            total -= size;
+        }
+        Ok(())
+    }
+}
+
+/// Hashes everything that determines what `script` produces: its command,
+/// arguments, environment, working directory, declared outputs, the contents
+/// of its input files and the keys of its dependencies, in order. Variables
+/// inherited from the environment `rdo` runs in are not part of the key.
+pub fn script_key(script: &Script, dependency_keys: &[String]) -> Result<String, Error> {
+    let mut hasher = Sha256::new();
+    hash_field(&mut hasher, script.name.as_bytes());
+    hash_field(
+        &mut hasher,
+        script.cmd.as_deref().unwrap_or_default().as_bytes(),
+    );
+    match &script.path {
+        Some(path) => {
+            hash_field(&mut hasher, path.as_bytes());
+            hash_field(&mut hasher, &fs::read(path)?);
+        }
+        None => hash_field(&mut hasher, b""),
+    }
+    hash_field(&mut hasher, format!("{:?}", script.script_type).as_bytes());
+    hash_list(&mut hasher, &script.args);
+    hash_list(
+        &mut hasher,
+        &script
+            .env
+            .iter()
+            .map(|(key, value)| format!("{}={}", key, value))
+            .collect::<Vec<_>>(),
+    );
+    hash_field(&mut hasher, &[script.inherit_env as u8]);
+    let cwd = script.cwd.as_deref().unwrap_or(Path::new(""));
+    hash_field(&mut hasher, cwd.to_string_lossy().as_bytes());
+    hash_list(&mut hasher, &script.outputs);
+
+    let inputs = expand_globs(script.inputs.as_deref().unwrap_or_default())?;
+    hasher.update((inputs.len() as u64).to_le_bytes());
+    for input in inputs {
+        hash_field(&mut hasher, input.to_string_lossy().as_bytes());
+        if !input.is_dir() {
+            hash_field(&mut hasher, &fs::read(&input)?);
+        }
+    }
+    hash_list(&mut hasher, dependency_keys);
+
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect())
+}
+
+/// Hashes `value` prefixed with its length, so that neighbouring fields
+/// cannot run into each other.
+fn hash_field(hasher: &mut Sha256, value: &[u8]) {
+    hasher.update((value.len() as u64).to_le_bytes());
+    hasher.update(value);
+}
+
+fn hash_list(hasher: &mut Sha256, values: &[String]) {
+    hasher.update((values.len() as u64).to_le_bytes());
+    for value in values {
+        hash_field(hasher, value.as_bytes());
+    }
+}
+
+/// Adds `path` to `files`, or every file below it if it is a directory.
+fn collect_files(path: &Path, files: &mut Vec<PathBuf>) -> Result<(), Error> {
+    if path.is_dir() {
+        for entry in fs::read_dir(path)? {
+            collect_files(&entry?.path(), files)?;
+        }
+    } else {
+        files.push(path.to_path_buf());
+    }
+    Ok(())
+}
+
+/// The total size of the files below `path`, which may not exist.
+fn dir_size(path: &Path) -> Result<u64, Error> {
+    let metadata = match fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+        Err(err) => return Err(Error::Io(err)),
+    };
+    if !metadata.is_dir() {
+        return Ok(metadata.len());
+    }
+
+    let mut size = 0;
+    for entry in fs::read_dir(path)? {
+        size += dir_size(&entry?.path())?;
+    }
+    Ok(size)
+}
+
+fn remove_dir_if_exists(path: &Path) -> Result<(), Error> {
+    match fs::remove_dir_all(path) {
+        Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(Error::Io(err)),
+        _ => Ok(()),
+    }
+}
+
+/// Formats a number of bytes for people, e.g. `1.5 MB`.
+pub fn format_size(bytes: u64) -> String {
+    let units = ["KB", "MB", "GB", "TB"];
+    if bytes < 1000 {
+        return format!("{} B", bytes);
+    }
+
+    let mut size = bytes as f64;
+    let mut unit = "B";
+    for next in units {
+        if size < 1000.0 {
+            break;
+        }
+        size /= 1000.0;
+        unit = next;
+    }
+    format!("{:.1} {}", size, unit)
+}
+
+/// The cache for the config in `config_dir`, kept in `.rdo/cache` next to
+/// the config file.
+pub fn load_cache_from_config(config: &Config, config_dir: &Path) -> Result<Cache, Error> {
+    Ok(Cache {
+        dir: get_state_dir(config_dir).join("cache"),
+        max_size: get_optional_size(config, "cache.max_size")?.unwrap_or(DEFAULT_MAX_SIZE),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{self, TempDir};
+
+    fn script(name: &str, dir: &Path) -> Script {
+        let mut script = test_support::script(name, &[]);
+        script.cwd = Some(dir.to_path_buf());
+        script.inputs = Some(vec![dir.join("in/*").to_string_lossy().to_string()]);
+        script.outputs = vec![dir.join("out").to_string_lossy().to_string()];
+        script
+    }
+
+    #[test]
+    fn test_script_key() {
+        let dir = TempDir::new("cache-key");
+        fs::create_dir_all(dir.join("in")).unwrap();
+        fs::write(dir.join("in/a.txt"), "a").unwrap();
+        let mut build = script("build", &dir);
+
+        let key = script_key(&build, &[]).unwrap();
+        assert_eq!(key.len(), 64);
+        assert_eq!(script_key(&build, &[]).unwrap(), key);
+        assert_ne!(script_key(&build, &["dep".to_string()]).unwrap(), key);
+
+        fs::write(dir.join("in/a.txt"), "b").unwrap();
+        let changed_input = script_key(&build, &[]).unwrap();
+        build.args = vec!["--release".to_string()];
+        let changed_args = script_key(&build, &[]).unwrap();
+
+        assert_ne!(changed_input, key);
+        assert_ne!(changed_args, changed_input);
+    }
+
+    #[test]
+    fn test_store_restore_and_evict() {
+        let dir = TempDir::new("cache");
+        fs::create_dir_all(dir.join("out/nested")).unwrap();
+        fs::write(dir.join("out/nested/app"), "0123456789").unwrap();
+        let build = script("build", &dir);
+        let mut cache = Cache {
+            dir: dir.join(".rdo/cache"),
+            max_size: 1 << 20,
+        };
+        let output = vec![CachedLine {
+            stream: OutputStream::Stdout,
+            line: "built".to_string(),
+        }];
+
+        assert_eq!(cache.restore("first").unwrap(), None);
+        cache.store("first", &build, &output).unwrap();
+        fs::remove_dir_all(dir.join("out")).unwrap();
+        let restored = cache.restore("first").unwrap();
+        let contents = fs::read_to_string(dir.join("out/nested/app")).unwrap();
+
+        cache.max_size = dir_size(&cache.dir.join("first")).unwrap();
+        // An entry that another script is still writing.
+        fs::create_dir_all(cache.dir.join("third.tmp")).unwrap();
+        fs::write(cache.dir.join("third.tmp/0"), [0; 1000]).unwrap();
+        cache.store("second", &build, &[]).unwrap();
+        let first_evicted = cache.restore("first").unwrap().is_none();
+        let in_progress_kept = cache.dir.join("third.tmp/0").exists();
+        let second_kept = cache.restore("second").unwrap().is_some();
+        let freed = cache.clean().unwrap();
+        let cleaned = !cache.dir.exists();
+
+        assert_eq!(restored, Some(output));
+        assert_eq!(contents, "0123456789");
+        assert!(first_evicted);
+        assert!(in_progress_kept);
+        assert!(second_kept);
+        assert!(freed > 0);
+        assert!(cleaned);
+    }
+
+    #[test]
+    fn test_format_size() {
+        assert_eq!(format_size(512), "512 B");
+        assert_eq!(format_size(1500), "1.5 KB");
+        assert_eq!(format_size(2_000_000_000), "2.0 GB");
+    }
+}
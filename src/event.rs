@@ -0,0 +1,149 @@
+use std::time::{Duration, SystemTime};
+
+use serde::{Serialize, Serializer};
+
+use crate::output::OutputLine;
+use crate::summary::ScriptReport;
+
+/// Something that happened during a run, sent from the executor and the
+/// running scripts to whatever is presenting the run.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "kebab-case")]
+pub enum Event {
+    /// The run is about to start the given scripts, in resolved order.
+    RunStarted {
+        scripts: Vec<String>,
+    },
+    /// The script is waiting for its dependencies or a free job slot.
+    ScriptQueued {
+        script: String,
+    },
+    /// The script has been started. Retries do not start it again.
+    ScriptStarted {
+        script: String,
+    },
+    Output(OutputLine),
+    /// The script has finished, or will not be run at all.
+    ScriptFinished(ScriptReport),
+    RunFinished {
+        success: bool,
+        #[serde(serialize_with = "serialize_duration")]
+        duration: Duration,
+        error: Option<String>,
+    },
+}
+
+/// An event and the time it happened, as sent to whatever is presenting the
+/// run. The time is taken when the event is created, not when it is shown.
+#[derive(Debug, Clone, Serialize)]
+pub struct TimedEvent {
+    #[serde(serialize_with = "serialize_timestamp")]
+    pub timestamp: SystemTime,
+    #[serde(flatten)]
+    pub event: Event,
+}
+
+impl TimedEvent {
+    /// Stamps `event` with the current time. Output lines keep the time they
+    /// were read.
+    pub fn new(event: Event) -> TimedEvent {
+        let timestamp = match &event {
+            Event::Output(output) => output.timestamp,
+            _ => SystemTime::now(),
+        };
+        TimedEvent { timestamp, event }
+    }
+
+    /// Renders the event as a single line of JSON with a `timestamp` field.
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("events are always serializable")
+    }
+}
+
+impl From<Event> for TimedEvent {
+    fn from(event: Event) -> TimedEvent {
+        TimedEvent::new(event)
+    }
+}
+
+/// Serializes a duration as fractional seconds.
+pub(crate) fn serialize_duration<S: Serializer>(
+    duration: &Duration,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_f64(duration.as_secs_f64())
+}
+
+pub(crate) fn serialize_optional_duration<S: Serializer>(
+    duration: &Option<Duration>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match duration {
+        Some(duration) => serialize_duration(duration, serializer),
+        None => serializer.serialize_none(),
+    }
+}
+
+/// Serializes a timestamp in RFC 3339 format, e.g. `2023-03-01T12:00:00.000Z`.
+pub(crate) fn serialize_timestamp<S: Serializer>(
+    timestamp: &SystemTime,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(&humantime::format_rfc3339_millis(*timestamp))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value;
+
+    use super::*;
+    use crate::output::OutputStream;
+    use crate::summary::ScriptStatus;
+
+    #[test]
+    fn test_output_event_to_json() {
+        let output = OutputLine::new("build", OutputStream::Stderr, "oops".to_string());
+        let timestamp = humantime::format_rfc3339_millis(output.timestamp).to_string();
+
+        let event = TimedEvent::new(Event::Output(output));
+        let json: Value = serde_json::from_str(&event.to_json()).unwrap();
+        assert_eq!(json["event"], "output");
+        assert_eq!(json["timestamp"], timestamp.as_str());
+        assert_eq!(json["script"], "build");
+        assert_eq!(json["stream"], "stderr");
+        assert_eq!(json["line"], "oops");
+    }
+
+    #[test]
+    fn test_script_finished_event_to_json() {
+        let report = ScriptReport {
+            name: "build".to_string(),
+            status: ScriptStatus::TimedOut,
+            exit_code: None,
+            duration: Some(Duration::from_millis(1500)),
+            attempts: 2,
+        };
+
+        let event = TimedEvent::new(Event::ScriptFinished(report));
+        let json: Value = serde_json::from_str(&event.to_json()).unwrap();
+        assert_eq!(json["event"], "script-finished");
+        assert_eq!(json["script"], "build");
+        assert_eq!(json["status"], "timed-out");
+        assert_eq!(json["exit_code"], Value::Null);
+        assert_eq!(json["duration"], 1.5);
+        assert_eq!(json["attempts"], 2);
+    }
+
+    #[test]
+    fn test_timestamp_is_taken_when_the_event_is_created() {
+        let event = TimedEvent::new(Event::ScriptStarted {
+            script: "build".to_string(),
+        });
+        let created = humantime::format_rfc3339_millis(event.timestamp).to_string();
+        std::thread::sleep(Duration::from_millis(20));
+
+        let json: Value = serde_json::from_str(&event.to_json()).unwrap();
+        assert_eq!(json["timestamp"], created.as_str());
+        assert_eq!(json["event"], "script-started");
+    }
+}
@@ -0,0 +1,1162 @@
+use std::collections::{HashMap, HashSet};
+use std::future::{pending, Future};
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use clap::ValueEnum;
+use config::{Config, ConfigError};
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+use tokio::sync::mpsc::Sender;
+use tokio::sync::watch;
+use tokio::sync::watch::Receiver as WatchReceiver;
+use tokio::task::{spawn_blocking, JoinSet};
+use tokio::time::{sleep, sleep_until, Instant};
+
+use crate::cache::{script_key, Cache, CachedLine};
+use crate::event::{Event, TimedEvent};
+use crate::output::OutputLine;
+use crate::resolver::Resolver;
+use crate::runnable::{wait_for_cancel, Runnable};
+use crate::script::{load_all_scripts_from_config, Script};
+use crate::state::RunState;
+use crate::summary::{RunSummary, ScriptReport, ScriptStatus};
+use crate::utils::config::{get_optional, get_optional_duration};
+use crate::utils::error::Error;
+
+/// What the executor does after a script fails.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Hash, Default, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum FailurePolicy {
+    /// Cancel all running scripts and start nothing else.
+    FailFast,
+    /// Run every remaining script, including dependents of the failed one.
+    KeepGoing,
+    /// Skip the dependents of the failed script but keep running the others.
+    #[default]
+    ContinueIndependent,
+}
+
+#[derive(Debug, Clone)]
+pub struct ExecutorOptions {
+    /// Maximum number of scripts running at the same time.
+    pub jobs: usize,
+    /// Failure policy for scripts that do not set their own `on_failure`.
+    pub on_failure: FailurePolicy,
+    /// Maximum duration of the whole run.
+    pub timeout: Option<Duration>,
+    /// File the outcome of each script is written to as soon as it finishes.
+    pub state_file: Option<PathBuf>,
+    /// State of a previous run to resume: scripts that succeeded in it are
+    /// not run again, unless one of their dependencies is.
+    pub resume_from: Option<RunState>,
+    /// Cache that scripts with `cache = true` are restored from and stored in.
+    pub cache: Option<Cache>,
+}
+
+impl Default for ExecutorOptions {
+    fn default() -> Self {
+        ExecutorOptions {
+            jobs: std::thread::available_parallelism()
+                .map(NonZeroUsize::get)
+                .unwrap_or(1),
+            on_failure: FailurePolicy::default(),
+            timeout: None,
+            state_file: None,
+            resume_from: None,
+            cache: None,
+        }
+    }
+}
+
+/// Runs scripts as soon as all of their dependencies have finished, keeping at
+/// most `jobs` of them running at once.
+pub struct Executor<'a> {
+    scripts: Vec<&'a Script>,
+    /// Every loaded script, so that cache keys also cover dependencies that
+    /// are not part of the run.
+    all_scripts: Vec<&'a Script>,
+    options: ExecutorOptions,
+}
+
+impl<'a> Executor<'a> {
+    /// `scripts` must be in topological order, as returned by the `Resolver`.
+    /// Dependencies that are not part of `scripts` are treated as satisfied.
+    pub fn new(
+        scripts: Vec<&'a Script>,
+        all_scripts: Vec<&'a Script>,
+        options: ExecutorOptions,
+    ) -> Executor<'a> {
+        Executor {
+            scripts,
+            all_scripts,
+            options,
+        }
+    }
+
+    /// Runs every script, handling failures according to each script's
+    /// `on_failure` policy, and reports the outcome of each one.
+    ///
+    /// Setting `cancel_rx` to `true` terminates all running scripts and stops
+    /// the run. The receiver of `output_tx` must be drained while the run is
+    /// going, or dropped: sending waits while the channel is full.
+    pub async fn run(
+        &self,
+        stdin_rx: WatchReceiver<String>,
+        output_tx: Sender<TimedEvent>,
+        mut cancel_rx: WatchReceiver<bool>,
+    ) -> RunSummary {
+        let started = Instant::now();
+        let names = self
+            .scripts
+            .iter()
+            .map(|s| s.name.as_str())
+            .collect::<HashSet<&str>>();
+        let mut pending = self.scripts.clone();
+        let mut finished = HashSet::new();
+        let mut not_run = HashSet::new();
+        let mut reports = HashMap::new();
+        let mut running = JoinSet::new();
+        let mut first_error = None;
+        let mut ran = HashSet::new();
+        let mut keys = HashMap::new();
+        let mut state = self.options.resume_from.clone().unwrap_or_default();
+        for script in &self.scripts {
+            state.scripts.remove(&script.name);
+        }
+
+        let (stop_tx, stop_rx) = watch::channel(false);
+        let deadline = self.options.timeout.map(|timeout| started + timeout);
+
+        send_event(
+            &output_tx,
+            Event::RunStarted {
+                scripts: self.scripts.iter().map(|s| s.name.clone()).collect(),
+            },
+        )
+        .await;
+        for script in &self.scripts {
+            let script = script.name.clone();
+            send_event(&output_tx, Event::ScriptQueued { script }).await;
+        }
+
+        loop {
+            let mut skipped = Vec::new();
+            pending.retain(|script| match find_not_run_dependency(script, &not_run) {
+                Some(dep) => {
+                    warn!(
+                        "{}",
+                        Error::ScriptDependencyNotRun(script.name.clone(), dep)
+                    );
+                    not_run.insert(script.name.clone());
+                    skipped.push(ScriptReport::not_run(&script.name, ScriptStatus::Skipped));
+                    false
+                }
+                None => true,
+            });
+            for report in skipped {
+                self.record_state(&mut state, &report);
+                send_event(&output_tx, Event::ScriptFinished(report.clone())).await;
+                reports.insert(report.name.clone(), report);
+            }
+
+            while running.len() < self.options.jobs {
+                let ready = pending.iter().position(|script| {
+                    script
+                        .dependencies
+                        .iter()
+                        .all(|dep| !names.contains(dep.as_str()) || finished.contains(dep))
+                });
+                let Some(index) = ready else {
+                    break;
+                };
+
+                let script = pending.remove(index);
+                let resume_from = self.options.resume_from.as_ref();
+                if let Some(status) = reason_to_skip(script, resume_from, &ran) {
+                    info!("Script {} is {}", script.name, status);
+                    let report = ScriptReport::not_run(&script.name, status);
+                    self.record_state(&mut state, &report);
+                    send_event(&output_tx, Event::ScriptFinished(report.clone())).await;
+                    reports.insert(script.name.clone(), report);
+                    finished.insert(script.name.clone());
+                    continue;
+                }
+
+                ran.insert(script.name.clone());
+                let cache = match (&self.options.cache, script.cache) {
+                    (Some(cache), true) => self
+                        .cache_key(script, &mut keys)
+                        .map(|key| (cache.clone(), key)),
+                    _ => None,
+                };
+                let script = script.clone();
+                let stdin_rx = stdin_rx.clone();
+                let output_tx = output_tx.clone();
+                let stop_rx = stop_rx.clone();
+                debug!("Scheduling script: {}", script.name);
+                running.spawn(async move {
+                    let event = Event::ScriptStarted {
+                        script: script.name.clone(),
+                    };
+                    send_event(&output_tx, event).await;
+
+                    // Restoring takes up a job like running the script would.
+                    if let Some((cache, key)) = &cache {
+                        if restore(&script, cache, key, &output_tx).await {
+                            let report = ScriptReport::not_run(&script.name, ScriptStatus::Cached);
+                            return (report, Ok(()));
+                        }
+                    }
+
+                    let started = Instant::now();
+                    let (result, attempts) = match cache {
+                        Some((cache, key)) => {
+                            run_and_store(&script, &cache, &key, stdin_rx, output_tx, stop_rx).await
+                        }
+                        None => run_with_retries(&script, stdin_rx, output_tx, stop_rx).await,
+                    };
+                    let report = ScriptReport::from_result(
+                        &script.name,
+                        &result,
+                        started.elapsed(),
+                        attempts,
+                    );
+                    (report, result)
+                });
+            }
+
+            let stopped = *stop_rx.borrow();
+            let joined = tokio::select! {
+                joined = running.join_next() => joined,
+                _ = sleep_until_deadline(deadline), if !stopped => {
+                    let timeout = self.options.timeout.unwrap_or_default();
+                    error!("Run timed out after {}", humantime::format_duration(timeout));
+                    first_error.get_or_insert(Error::RunTimedOut(timeout));
+                    stop(&stop_tx, &mut pending);
+                    continue;
+                }
+                _ = wait_for_cancel(&mut cancel_rx), if !stopped => {
+                    warn!("Run cancelled");
+                    first_error.get_or_insert(Error::RunCancelled);
+                    stop(&stop_tx, &mut pending);
+                    continue;
+                }
+            };
+            let Some(joined) = joined else {
+                break;
+            };
+
+            let (report, result) = match joined {
+                Ok(joined) => joined,
+                Err(e) => {
+                    error!("Script task failed: {}", e);
+                    first_error.get_or_insert(Error::Unspecified(e.to_string()));
+                    continue;
+                }
+            };
+            let name = report.name.clone();
+            self.record_state(&mut state, &report);
+            send_event(&output_tx, Event::ScriptFinished(report.clone())).await;
+            reports.insert(name.clone(), report);
+
+            match result {
+                Ok(()) => {
+                    finished.insert(name);
+                }
+                Err(e @ Error::ScriptCancelled(_)) => {
+                    warn!("{}", e);
+                    not_run.insert(name);
+                }
+                Err(e) => {
+                    error!("Error running script {}: {}", name, e);
+                    first_error.get_or_insert(e);
+
+                    match self.failure_policy(&name) {
+                        FailurePolicy::FailFast => {
+                            warn!("Stopping run after failure of script {}", name);
+                            stop(&stop_tx, &mut pending);
+                        }
+                        FailurePolicy::KeepGoing => {
+                            finished.insert(name);
+                        }
+                        FailurePolicy::ContinueIndependent => {
+                            not_run.insert(name);
+                        }
+                    }
+                }
+            }
+        }
+
+        for script in pending {
+            warn!("Script {} was not run", script.name);
+        }
+
+        let mut scripts = Vec::new();
+        for script in &self.scripts {
+            let report = match reports.remove(&script.name) {
+                Some(report) => report,
+                None => {
+                    let report = ScriptReport::not_run(&script.name, ScriptStatus::Cancelled);
+                    send_event(&output_tx, Event::ScriptFinished(report.clone())).await;
+                    report
+                }
+            };
+            scripts.push(report);
+        }
+
+        let summary = RunSummary {
+            scripts,
+            duration: started.elapsed(),
+            error: first_error,
+        };
+        let event = Event::RunFinished {
+            success: summary.is_success(),
+            duration: summary.duration,
+            error: summary.error.as_ref().map(|e| e.to_string()),
+        };
+        send_event(&output_tx, event).await;
+        summary
+    }
+
+    /// The cache key of `script`, or `None` if it cannot be computed. Keys of
+    /// its dependencies, whether they are part of the run or not, are
+    /// computed as needed and kept in `keys`.
+    fn cache_key(
+        &self,
+        script: &Script,
+        keys: &mut HashMap<String, Option<String>>,
+    ) -> Option<String> {
+        if let Some(key) = keys.get(&script.name) {
+            return key.clone();
+        }
+
+        let mut dependency_keys = Vec::new();
+        for dep in &script.dependencies {
+            let dep = self.all_scripts.iter().find(|s| &s.name == dep)?;
+            dependency_keys.push(self.cache_key(dep, keys)?);
+        }
+        let key = script_key(script, &dependency_keys)
+            .map_err(|e| warn!("Cannot compute cache key of script {}: {}", script.name, e))
+            .ok();
+        keys.insert(script.name.clone(), key.clone());
+        key
+    }
+
+    fn record_state(&self, state: &mut RunState, report: &ScriptReport) {
+        let Some(state_file) = &self.options.state_file else {
+            return;
+        };
+
+        state.scripts.insert(report.name.clone(), report.status);
+        if let Err(e) = state.save(state_file) {
+            warn!(
+                "Failed to save run state to {}: {}",
+                state_file.display(),
+                e
+            );
+        }
+    }
+
+    fn failure_policy(&self, name: &str) -> FailurePolicy {
+        self.scripts
+            .iter()
+            .find(|script| script.name == name)
+            .and_then(|script| script.on_failure)
+            .unwrap_or(self.options.on_failure)
+    }
+}
+
+/// The status to report if `script` can be skipped: none of the scripts it
+/// depends on are in `ran`, and it either succeeded in the run being resumed
+/// (`Resumed`) or its outputs are newer than its inputs (`UpToDate`).
+pub fn reason_to_skip(
+    script: &Script,
+    resume_from: Option<&RunState>,
+    ran: &HashSet<String>,
+) -> Option<ScriptStatus> {
+    if script.dependencies.iter().any(|dep| ran.contains(dep)) {
+        return None;
+    }
+    if resume_from.is_some_and(|previous| previous.is_done(&script.name)) {
+        return Some(ScriptStatus::Resumed);
+    }
+
+    let fresh = script.outputs_are_fresh().unwrap_or_else(|e| {
+        warn!("Cannot check outputs of script {}: {}", script.name, e);
+        false
+    });
+    fresh.then_some(ScriptStatus::UpToDate)
+}
+
+/// Sends `event` to whoever is presenting the run, waiting while the channel
+/// is full. Events are dropped if nobody is listening any more.
+async fn send_event(output_tx: &Sender<TimedEvent>, event: Event) {
+    if output_tx.send(TimedEvent::new(event)).await.is_err() {
+        debug!("Dropped event: output channel closed");
+    }
+}
+
+/// Restores the outputs of `script` from the cache and replays what it
+/// printed. Returns whether there was anything to restore.
+async fn restore(
+    script: &Script,
+    cache: &Cache,
+    key: &str,
+    output_tx: &Sender<TimedEvent>,
+) -> bool {
+    let (cache, key) = (cache.clone(), key.to_string());
+    let restored = spawn_blocking(move || cache.restore(&key))
+        .await
+        .map_err(|e| Error::Unspecified(e.to_string()))
+        .and_then(|result| result);
+    let output = match restored {
+        Ok(Some(output)) => output,
+        Ok(None) => return false,
+        Err(e) => {
+            warn!("Cannot restore script {} from cache: {}", script.name, e);
+            return false;
+        }
+    };
+
+    info!("Restored script {} from cache", script.name);
+    for CachedLine { stream, line } in output {
+        let line = OutputLine::new(&script.name, stream, line);
+        send_event(output_tx, Event::Output(line)).await;
+    }
+    true
+}
+
+/// Terminates all running scripts and drops the ones that have not started.
+fn stop(stop_tx: &watch::Sender<bool>, pending: &mut Vec<&Script>) {
+    stop_tx.send_replace(true);
+    for script in pending.drain(..) {
+        warn!("Script {} was not run", script.name);
+    }
+}
+
+async fn sleep_until_deadline(deadline: Option<Instant>) {
+    match deadline {
+        Some(deadline) => sleep_until(deadline).await,
+        None => pending().await,
+    }
+}
+
+/// Runs `script` until it succeeds or has used up all of its retries. Returns
+/// the result of the last attempt and the number of attempts made.
+async fn run_with_retries(
+    script: &Script,
+    stdin_rx: WatchReceiver<String>,
+    output_tx: Sender<TimedEvent>,
+    cancel_rx: WatchReceiver<bool>,
+) -> (Result<(), Error>, u32) {
+    retry(script, cancel_rx.clone(), || {
+        script.run(stdin_rx.clone(), output_tx.clone(), cancel_rx.clone())
+    })
+    .await
+}
+
+/// Calls `attempt_fn` until it succeeds or `script` has used up all of its
+/// retries, waiting for the script's retry delay in between.
+async fn retry<T, F, Fut>(
+    script: &Script,
+    mut cancel_rx: WatchReceiver<bool>,
+    mut attempt_fn: F,
+) -> (Result<T, Error>, u32)
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, Error>>,
+{
+    let attempts = script.retries.saturating_add(1);
+    let mut attempt = 1;
+    loop {
+        if attempts > 1 {
+            info!("Script {}: attempt {}/{}", script.name, attempt, attempts);
+        }
+
+        match attempt_fn().await {
+            Err(Error::ScriptCancelled(name)) => {
+                return (Err(Error::ScriptCancelled(name)), attempt)
+            }
+            Err(e) if attempt < attempts => {
+                let delay = script.retry_delay_for(attempt);
+                warn!(
+                    "Script {} failed on attempt {}/{}: {}; retrying in {}",
+                    script.name,
+                    attempt,
+                    attempts,
+                    e,
+                    humantime::format_duration(delay)
+                );
+                tokio::select! {
+                    _ = sleep(delay) => {}
+                    _ = wait_for_cancel(&mut cancel_rx) => {
+                        return (Err(Error::ScriptCancelled(script.name.clone())), attempt);
+                    }
+                }
+                attempt += 1;
+            }
+            result => return (result, attempt),
+        }
+    }
+}
+
+/// Runs `script` like `run_with_retries`, storing its results in `cache` under
+/// `key` once it succeeds. Only the output of the successful attempt is stored.
+async fn run_and_store(
+    script: &Script,
+    cache: &Cache,
+    key: &str,
+    stdin_rx: WatchReceiver<String>,
+    output_tx: Sender<TimedEvent>,
+    cancel_rx: WatchReceiver<bool>,
+) -> (Result<(), Error>, u32) {
+    let (result, attempts) = retry(script, cancel_rx.clone(), || {
+        run_and_capture(script, stdin_rx.clone(), &output_tx, cancel_rx.clone())
+    })
+    .await;
+    let output = match result {
+        Ok(output) => output,
+        Err(e) => return (Err(e), attempts),
+    };
+
+    let (cache, key, owned_script) = (cache.clone(), key.to_string(), script.clone());
+    let stored = spawn_blocking(move || cache.store(&key, &owned_script, &output))
+        .await
+        .map_err(|e| Error::Unspecified(e.to_string()))
+        .and_then(|result| result);
+    match stored {
+        Ok(()) => debug!("Stored results of script {} in cache", script.name),
+        Err(e) => warn!(
+            "Cannot store results of script {} in cache: {}",
+            script.name, e
+        ),
+    }
+    (Ok(()), attempts)
+}
+
+/// Runs a single attempt of `script`, passing its events on to `output_tx` and
+/// returning the lines it printed if it succeeded.
+async fn run_and_capture(
+    script: &Script,
+    stdin_rx: WatchReceiver<String>,
+    output_tx: &Sender<TimedEvent>,
+    cancel_rx: WatchReceiver<bool>,
+) -> Result<Vec<CachedLine>, Error> {
+    let (capture_tx, mut capture_rx) = mpsc::channel::<TimedEvent>(100);
+    let capture = async {
+        let mut output = Vec::new();
+        while let Some(event) = capture_rx.recv().await {
+            if let Event::Output(line) = &event.event {
+                output.push(CachedLine::from(line));
+            }
+            if output_tx.send(event).await.is_err() {
+                debug!("Dropped event: output channel closed");
+            }
+        }
+        output
+    };
+
+    let (result, output) = tokio::join!(script.run(stdin_rx, capture_tx, cancel_rx), capture);
+    result.map(|()| output)
+}
+
+fn find_not_run_dependency(script: &Script, not_run: &HashSet<String>) -> Option<String> {
+    script
+        .dependencies
+        .iter()
+        .find(|dep| not_run.contains(*dep))
+        .cloned()
+}
+
+/// The scripts a run is built around.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum Targets {
+    /// All enabled scripts.
+    #[default]
+    All,
+    /// The given scripts and their dependencies.
+    Scripts(Vec<String>),
+    /// The given scripts and everything that depends on them.
+    Downstream(Vec<String>),
+}
+
+/// Which scripts a run includes: the targets, narrowed down by filters.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Selection {
+    pub targets: Targets,
+    /// Leave out the dependencies of `Targets::Scripts`.
+    pub only: bool,
+    /// Only run this script and the selected scripts that depend on it.
+    pub from: Option<String>,
+    /// Scripts to leave out. Scripts that depend on them still run.
+    pub skip: Vec<String>,
+}
+
+/// Loads the scripts in `config`, resolves the selected scripts, and runs them.
+/// As with [`Executor::run`], the receiver of `output_tx` must be drained.
+pub async fn run_scripts_from_config(
+    config: &Config,
+    config_dir: &Path,
+    selection: Selection,
+    options: ExecutorOptions,
+    stdin_rx: WatchReceiver<String>,
+    output_tx: Sender<TimedEvent>,
+    cancel_rx: WatchReceiver<bool>,
+) -> Result<RunSummary, Error> {
+    let scripts = load_all_scripts_from_config(config, config_dir)?;
+    let resolver = Resolver::new(scripts.iter().collect())?;
+    let sorted = resolve_scripts(&resolver, selection)?;
+
+    Ok(Executor::new(sorted, scripts.iter().collect(), options)
+        .run(stdin_rx, output_tx, cancel_rx)
+        .await)
+}
+
+/// Resolves the selected scripts in the order they would run.
+pub fn resolve_scripts<'a>(
+    resolver: &'a Resolver<'a, Script, String>,
+    selection: Selection,
+) -> Result<Vec<&'a Script>, Error> {
+    let mut scripts = match &selection.targets {
+        Targets::All => resolver.resolve_all()?,
+        Targets::Scripts(script_names) => resolver.resolve(script_names.clone())?,
+        Targets::Downstream(script_names) => resolver.resolve_dependents(script_names.clone())?,
+    };
+
+    if let (true, Targets::Scripts(script_names)) = (selection.only, &selection.targets) {
+        scripts.retain(|script| script_names.contains(&script.name));
+    }
+    if let Some(from) = &selection.from {
+        scripts = resolver.filter_dependents_of(scripts, from)?;
+    }
+    for name in &selection.skip {
+        resolver.find(name)?;
+    }
+    scripts.retain(|script| !selection.skip.contains(&script.name));
+
+    Ok(scripts)
+}
+
+pub fn load_executor_options_from_config(config: &Config) -> Result<ExecutorOptions, Error> {
+    let mut options = ExecutorOptions::default();
+    if let Some(jobs) = get_optional(config, "run.jobs")? {
+        options.jobs = jobs;
+    }
+    if let Some(on_failure) = get_optional(config, "run.on_failure")? {
+        options.on_failure = on_failure;
+    }
+    options.timeout = get_optional_duration(config, "run.timeout")?;
+
+    validate_jobs(options.jobs)?;
+    Ok(options)
+}
+
+pub fn validate_jobs(jobs: usize) -> Result<(), Error> {
+    if jobs == 0 {
+        return Err(Error::Config(ConfigError::Message(
+            "jobs must be at least 1".to_string(),
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::SystemTime;
+
+    use tokio::sync::mpsc;
+
+    use super::*;
+    use crate::test_support::{cmd_script, TempDir};
+
+    fn options(jobs: usize, on_failure: FailurePolicy) -> ExecutorOptions {
+        ExecutorOptions {
+            jobs,
+            on_failure,
+            ..Default::default()
+        }
+    }
+
+    async fn run_scripts(
+        scripts: &[Script],
+        options: ExecutorOptions,
+    ) -> (RunSummary, Vec<String>) {
+        let (_stdin_tx, stdin_rx) = watch::channel(String::new());
+        let (output_tx, mut output_rx) = mpsc::channel(100);
+        let (_cancel_tx, cancel_rx) = watch::channel(false);
+        let executor = Executor::new(scripts.iter().collect(), scripts.iter().collect(), options);
+
+        let summary = executor.run(stdin_rx, output_tx, cancel_rx).await;
+        let mut lines = Vec::new();
+        while let Some(event) = output_rx.recv().await {
+            if let Event::Output(output) = event.event {
+                lines.push(output.line);
+            }
+        }
+        (summary, lines)
+    }
+
+    /// Runs `scripts` and describes every event they send, one per line.
+    async fn run_events(scripts: &[Script], options: ExecutorOptions) -> Vec<String> {
+        let (_stdin_tx, stdin_rx) = watch::channel(String::new());
+        let (output_tx, mut output_rx) = mpsc::channel(100);
+        let (_cancel_tx, cancel_rx) = watch::channel(false);
+        let executor = Executor::new(scripts.iter().collect(), scripts.iter().collect(), options);
+        executor.run(stdin_rx, output_tx, cancel_rx).await;
+
+        let mut events = Vec::new();
+        while let Some(event) = output_rx.recv().await {
+            events.push(match event.event {
+                Event::RunStarted { scripts } => format!("run started {}", scripts.join(",")),
+                Event::ScriptQueued { script } => format!("queued {}", script),
+                Event::ScriptStarted { script } => format!("started {}", script),
+                Event::Output(output) => format!("output {} {}", output.script, output.line),
+                Event::ScriptFinished(report) => {
+                    format!("finished {} {}", report.name, report.status)
+                }
+                Event::RunFinished { success, .. } => format!("run finished {}", success),
+            });
+        }
+        events
+    }
+
+    #[test]
+    fn test_selection_filters() {
+        let scripts = [
+            cmd_script("fetch", "true", &[]),
+            cmd_script("build", "true", &["fetch"]),
+            cmd_script("lint", "true", &["fetch"]),
+            cmd_script("test", "true", &["build"]),
+            cmd_script("deploy", "true", &["test", "lint"]),
+        ];
+        let resolver = Resolver::new(scripts.iter().collect()).unwrap();
+        let resolve = |selection: Selection| {
+            resolve_scripts(&resolver, selection)
+                .unwrap()
+                .iter()
+                .map(|s| s.name.clone())
+                .collect::<Vec<_>>()
+        };
+        let targets = Targets::Scripts(vec!["deploy".to_string(), "lint".to_string()]);
+
+        let only = resolve(Selection {
+            targets: targets.clone(),
+            only: true,
+            ..Default::default()
+        });
+        assert_eq!(only, ["lint", "deploy"]);
+
+        let mut from = resolve(Selection {
+            targets: targets.clone(),
+            from: Some("build".to_string()),
+            ..Default::default()
+        });
+        from.sort();
+        assert_eq!(from, ["build", "deploy", "test"]);
+
+        let mut skip = resolve(Selection {
+            targets,
+            skip: vec!["fetch".to_string(), "test".to_string()],
+            ..Default::default()
+        });
+        skip.sort();
+        assert_eq!(skip, ["build", "deploy", "lint"]);
+    }
+
+    #[tokio::test]
+    async fn test_independent_scripts_run_in_parallel() {
+        // Each script waits until all of them have started, which only
+        // happens if they run at the same time.
+        let dir = TempDir::new("parallel");
+        let rendezvous = |name: &str| {
+            format!(
+                "cd {}; touch {}; for i in $(seq 100); do \
+                 test -f a && test -f b && test -f c && exit 0; sleep 0.1; done; exit 1",
+                dir.display(),
+                name
+            )
+        };
+        let scripts = vec![
+            cmd_script("a", &rendezvous("a"), &[]),
+            cmd_script("b", &rendezvous("b"), &[]),
+            cmd_script("c", &rendezvous("c"), &[]),
+        ];
+
+        let (summary, _) = run_scripts(&scripts, options(3, FailurePolicy::default())).await;
+        assert!(summary.is_success());
+    }
+
+    #[tokio::test]
+    async fn test_dependencies_finish_first() {
+        let dir = TempDir::new("first");
+        let marker = dir.join("marker");
+        let scripts = vec![
+            cmd_script(
+                "first",
+                &format!("echo first; touch {}", marker.display()),
+                &[],
+            ),
+            cmd_script(
+                "second",
+                &format!("test -f {} && echo second", marker.display()),
+                &["first"],
+            ),
+        ];
+
+        let (summary, lines) = run_scripts(&scripts, options(4, FailurePolicy::default())).await;
+        assert!(summary.is_success());
+        assert_eq!(lines, vec!["first", "second"]);
+    }
+
+    #[tokio::test]
+    async fn test_failure_skips_dependents_only() {
+        let scripts = vec![
+            cmd_script("failing", "exit 3", &[]),
+            cmd_script("independent", "echo independent", &[]),
+            cmd_script("dependent", "echo dependent", &["failing"]),
+            cmd_script("transitive", "echo transitive", &["dependent"]),
+        ];
+
+        let (summary, lines) = run_scripts(&scripts, options(1, FailurePolicy::default())).await;
+        match summary.error {
+            Some(Error::ScriptFailed(name, code, signal)) => {
+                assert_eq!(name, "failing");
+                assert_eq!(code, Some(3));
+                assert_eq!(signal, None);
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+        assert_eq!(lines, vec!["independent"]);
+
+        let statuses = summary.scripts.iter().map(|s| s.status).collect::<Vec<_>>();
+        assert_eq!(
+            statuses,
+            vec![
+                ScriptStatus::Failed,
+                ScriptStatus::Succeeded,
+                ScriptStatus::Skipped,
+                ScriptStatus::Skipped
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_events_cover_every_script() {
+        let scripts = [
+            cmd_script("ok", "echo ok", &[]),
+            cmd_script("failing", "exit 1", &[]),
+            cmd_script("dependent", "echo dependent", &["failing"]),
+        ];
+
+        let events = run_events(&scripts, options(1, FailurePolicy::default())).await;
+        assert_eq!(
+            events,
+            [
+                "run started ok,failing,dependent",
+                "queued ok",
+                "queued failing",
+                "queued dependent",
+                "started ok",
+                "output ok ok",
+                "finished ok succeeded",
+                "started failing",
+                "finished failing failed",
+                "finished dependent skipped",
+                "run finished false",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_resume_skips_scripts_that_succeeded() {
+        let scripts = [
+            cmd_script("a", "echo a", &[]),
+            cmd_script("b", "echo b", &["a"]),
+            cmd_script("c", "echo c", &["b"]),
+            cmd_script("d", "echo d", &["a"]),
+        ];
+        let mut previous = RunState::default();
+        for (name, status) in [
+            ("a", ScriptStatus::Succeeded),
+            ("b", ScriptStatus::Failed),
+            ("c", ScriptStatus::Succeeded),
+            ("d", ScriptStatus::UpToDate),
+        ] {
+            previous.scripts.insert(name.to_string(), status);
+        }
+        let dir = TempDir::new("resume");
+        let state_file = dir.join("config.state.json");
+        let options = ExecutorOptions {
+            jobs: 1,
+            state_file: Some(state_file.clone()),
+            resume_from: Some(previous),
+            ..Default::default()
+        };
+
+        let (summary, lines) = run_scripts(&scripts, options).await;
+        let state = RunState::load(&state_file).unwrap();
+
+        assert!(summary.is_success());
+        assert_eq!(lines, ["b", "c"]);
+        let statuses = state.scripts.values().copied().collect::<Vec<_>>();
+        assert_eq!(
+            statuses,
+            [
+                ScriptStatus::Resumed,
+                ScriptStatus::Succeeded,
+                ScriptStatus::Succeeded,
+                ScriptStatus::Resumed
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_scripts_with_fresh_outputs_are_skipped() {
+        let dir = TempDir::new("outputs");
+        std::fs::write(dir.join("input"), "").unwrap();
+        std::fs::write(dir.join("output"), "").unwrap();
+        let input = std::fs::File::options()
+            .write(true)
+            .open(dir.join("input"))
+            .unwrap();
+        input
+            .set_modified(SystemTime::now() - Duration::from_secs(10))
+            .unwrap();
+
+        let with_files = |mut script: Script| {
+            script.inputs = Some(vec![dir.join("input").to_string_lossy().to_string()]);
+            script.outputs = vec![dir.join("output").to_string_lossy().to_string()];
+            script
+        };
+        let scripts = [
+            cmd_script("stale", "echo stale", &[]),
+            with_files(cmd_script("fresh", "echo fresh", &[])),
+            with_files(cmd_script("after_fresh", "echo after_fresh", &["fresh"])),
+            with_files(cmd_script("after_stale", "echo after_stale", &["stale"])),
+        ];
+
+        let (summary, lines) = run_scripts(&scripts, options(1, FailurePolicy::default())).await;
+
+        assert!(summary.is_success());
+        assert_eq!(lines, ["stale", "after_stale"]);
+        let statuses = summary.scripts.iter().map(|s| s.status).collect::<Vec<_>>();
+        assert_eq!(
+            statuses,
+            [
+                ScriptStatus::Succeeded,
+                ScriptStatus::UpToDate,
+                ScriptStatus::UpToDate,
+                ScriptStatus::Succeeded
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_cached_scripts_are_restored() {
+        let dir = TempDir::new("cached");
+        let mut build = cmd_script("build", "echo building; echo built >> out", &[]);
+        build.cwd = Some(dir.to_path_buf());
+        build.outputs = vec![dir.join("out").to_string_lossy().to_string()];
+        build.cache = true;
+        let scripts = [build, cmd_script("test", "cat out", &["build"])];
+        let scripts = scripts.map(|mut script| {
+            script.cwd = Some(dir.to_path_buf());
+            script
+        });
+        let options = ExecutorOptions {
+            jobs: 1,
+            cache: Some(Cache {
+                dir: dir.join(".rdo/cache"),
+                max_size: 1 << 20,
+            }),
+            ..Default::default()
+        };
+
+        let (first, first_lines) = run_scripts(&scripts, options.clone()).await;
+        std::fs::remove_file(dir.join("out")).unwrap();
+        let (second, second_lines) = run_scripts(&scripts, options).await;
+
+        assert!(first.is_success());
+        assert!(second.is_success());
+        assert_eq!(first_lines, ["building", "built"]);
+        assert_eq!(second_lines, ["building", "built"]);
+        assert_eq!(second.scripts[0].status, ScriptStatus::Cached);
+        assert_eq!(second.scripts[1].status, ScriptStatus::Succeeded);
+    }
+
+    #[tokio::test]
+    async fn test_cache_keeps_output_of_the_successful_attempt() {
+        let dir = TempDir::new("cached-retry");
+        let mut flaky = cmd_script(
+            "flaky",
+            "test -f marker && echo ok && exit 0; echo failed; touch marker; exit 1",
+            &[],
+        );
+        flaky.cwd = Some(dir.to_path_buf());
+        flaky.retries = 1;
+        flaky.retry_delay = Duration::from_millis(10);
+        flaky.cache = true;
+        let options = ExecutorOptions {
+            jobs: 1,
+            cache: Some(Cache {
+                dir: dir.join(".rdo/cache"),
+                max_size: 1 << 20,
+            }),
+            ..Default::default()
+        };
+
+        let (first, first_lines) = run_scripts(&[flaky.clone()], options.clone()).await;
+        let second = run_events(&[flaky], options).await;
+
+        assert!(first.is_success());
+        assert_eq!(first_lines, ["failed", "ok"]);
+        assert_eq!(
+            second,
+            [
+                "run started flaky",
+                "queued flaky",
+                "started flaky",
+                "output flaky ok",
+                "finished flaky cached",
+                "run finished true",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_cache_key_covers_dependencies_outside_the_run() {
+        let dir = TempDir::new("cached-deps");
+        let mut build = cmd_script("build", "echo building", &["generate"]);
+        build.cache = true;
+        let options = ExecutorOptions {
+            jobs: 1,
+            cache: Some(Cache {
+                dir: dir.join(".rdo/cache"),
+                max_size: 1 << 20,
+            }),
+            ..Default::default()
+        };
+        let run_build = |generate: Script| {
+            let build = &build;
+            let options = options.clone();
+            async move {
+                let (_stdin_tx, stdin_rx) = watch::channel(String::new());
+                let (output_tx, _output_rx) = mpsc::channel(100);
+                let (_cancel_tx, cancel_rx) = watch::channel(false);
+                let executor = Executor::new(vec![build], vec![&generate, build], options);
+                executor.run(stdin_rx, output_tx, cancel_rx).await.scripts[0].status
+            }
+        };
+
+        let first = run_build(cmd_script("generate", "echo v1", &[])).await;
+        let unchanged = run_build(cmd_script("generate", "echo v1", &[])).await;
+        let changed = run_build(cmd_script("generate", "echo v2", &[])).await;
+
+        assert_eq!(first, ScriptStatus::Succeeded);
+        assert_eq!(unchanged, ScriptStatus::Cached);
+        assert_eq!(changed, ScriptStatus::Succeeded);
+    }
+
+    #[tokio::test]
+    async fn test_fail_fast_cancels_running_scripts() {
+        let scripts = vec![
+            cmd_script("failing", "sleep 0.2; exit 1", &[]),
+            cmd_script("slow", "sleep 5; echo slow", &[]),
+            cmd_script("later", "echo later", &[]),
+        ];
+
+        let (summary, lines) = run_scripts(&scripts, options(2, FailurePolicy::FailFast)).await;
+        let statuses = summary.scripts.iter().map(|s| s.status).collect::<Vec<_>>();
+        assert!(matches!(summary.error, Some(Error::ScriptFailed(..))));
+        // "slow" would have printed its line had it not been terminated.
+        assert!(lines.is_empty());
+        assert_eq!(
+            statuses,
+            [
+                ScriptStatus::Failed,
+                ScriptStatus::Cancelled,
+                ScriptStatus::Cancelled
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_keep_going_runs_dependents() {
+        let scripts = vec![
+            cmd_script("failing", "exit 1", &[]),
+            cmd_script("dependent", "echo dependent", &["failing"]),
+        ];
+
+        let (summary, lines) = run_scripts(&scripts, options(1, FailurePolicy::KeepGoing)).await;
+        assert!(matches!(summary.error, Some(Error::ScriptFailed(..))));
+        assert_eq!(lines, vec!["dependent"]);
+    }
+
+    #[tokio::test]
+    async fn test_script_policy_overrides_default() {
+        let mut failing = cmd_script("failing", "exit 1", &[]);
+        failing.on_failure = Some(FailurePolicy::KeepGoing);
+        let scripts = vec![
+            failing,
+            cmd_script("dependent", "echo dependent", &["failing"]),
+        ];
+
+        let (summary, lines) = run_scripts(&scripts, options(1, FailurePolicy::FailFast)).await;
+        assert!(!summary.is_success());
+        assert_eq!(lines, vec!["dependent"]);
+    }
+
+    #[tokio::test]
+    async fn test_retries_until_success() {
+        let dir = TempDir::new("retry");
+        let marker = dir.join("marker");
+        let mut flaky = cmd_script(
+            "flaky",
+            &format!(
+                "test -f {0} && echo ok && exit 0; touch {0}; exit 1",
+                marker.display()
+            ),
+            &[],
+        );
+        flaky.retries = 2;
+        flaky.retry_delay = Duration::from_millis(10);
+
+        let (summary, lines) = run_scripts(&[flaky], options(1, FailurePolicy::FailFast)).await;
+        assert!(summary.is_success());
+        assert_eq!(lines, vec!["ok"]);
+        assert_eq!(summary.scripts[0].attempts, 2);
+    }
+
+    #[tokio::test]
+    async fn test_run_timeout_cancels_scripts() {
+        let scripts = vec![
+            cmd_script("slow", "sleep 5; echo slow", &[]),
+            cmd_script("after", "echo after", &["slow"]),
+        ];
+        let options = ExecutorOptions {
+            timeout: Some(Duration::from_millis(200)),
+            ..options(1, FailurePolicy::default())
+        };
+
+        let (summary, lines) = run_scripts(&scripts, options).await;
+        assert!(matches!(summary.error, Some(Error::RunTimedOut(_))));
+        assert!(lines.is_empty());
+        assert_eq!(summary.count(ScriptStatus::Cancelled), 2);
+    }
+
+    #[tokio::test]
+    async fn test_script_timeout_kills_after_grace_period() {
+        // The ignored TERM is inherited by `sleep`, so only SIGKILL stops it
+        // before it prints.
+        let mut stubborn = cmd_script("stubborn", "trap '' TERM; sleep 5; echo survived", &[]);
+        stubborn.timeout = Some(Duration::from_millis(100));
+        stubborn.grace_period = Duration::from_millis(100);
+
+        let (summary, lines) = run_scripts(&[stubborn], options(1, FailurePolicy::default())).await;
+        assert!(matches!(summary.error, Some(Error::ScriptTimedOut(..))));
+        assert!(lines.is_empty());
+        assert_eq!(summary.scripts[0].status, ScriptStatus::TimedOut);
+    }
+}
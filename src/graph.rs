@@ -0,0 +1,141 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use clap::ValueEnum;
+
+use crate::script::Script;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum GraphFormat {
+    /// Graphviz DOT.
+    #[default]
+    Dot,
+    Mermaid,
+}
+
+/// Renders scripts and their `(dependency, dependent)` edges, so arrows point
+/// in the order scripts run. Disabled scripts are drawn dashed and grey.
+pub fn render_graph(
+    scripts: &[&Script],
+    edges: &[(&Script, &Script)],
+    format: GraphFormat,
+) -> String {
+    let mut scripts = scripts.to_vec();
+    scripts.sort_by(|a, b| a.name.cmp(&b.name));
+    let mut edges = edges
+        .iter()
+        .map(|(from, to)| (from.name.as_str(), to.name.as_str()))
+        .collect::<Vec<_>>();
+    edges.sort();
+
+    match format {
+        GraphFormat::Dot => render_dot(&scripts, &edges),
+        GraphFormat::Mermaid => render_mermaid(&scripts, &edges),
+    }
+}
+
+fn render_dot(scripts: &[&Script], edges: &[(&str, &str)]) -> String {
+    let quote = |name: &str| format!("\"{}\"", name.replace('\\', "\\\\").replace('"', "\\\""));
+
+    let mut dot = String::new();
+    writeln!(dot, "digraph rdo {{").unwrap();
+    for script in scripts {
+        if script.enabled {
+            writeln!(dot, "    {};", quote(&script.name)).unwrap();
+        } else {
+            writeln!(
+                dot,
+                "    {} [style=dashed, color=gray, fontcolor=gray];",
+                quote(&script.name)
+            )
+            .unwrap();
+        }
+    }
+    for (from, to) in edges {
+        writeln!(dot, "    {} -> {};", quote(from), quote(to)).unwrap();
+    }
+    writeln!(dot, "}}").unwrap();
+    dot
+}
+
+fn render_mermaid(scripts: &[&Script], edges: &[(&str, &str)]) -> String {
+    // Script names may contain characters Mermaid does not allow in ids, so
+    // nodes get generated ids and the name as their label.
+    let ids = scripts
+        .iter()
+        .enumerate()
+        .map(|(i, script)| (script.name.as_str(), format!("s{}", i)))
+        .collect::<HashMap<_, _>>();
+
+    let mut mermaid = String::new();
+    writeln!(mermaid, "graph TD").unwrap();
+    for script in scripts {
+        let label = script.name.replace('"', "#quot;");
+        let class = if script.enabled { "" } else { ":::disabled" };
+        writeln!(
+            mermaid,
+            "    {}[\"{}\"]{}",
+            ids[script.name.as_str()],
+            label,
+            class
+        )
+        .unwrap();
+    }
+    for (from, to) in edges {
+        writeln!(mermaid, "    {} --> {}", ids[from], ids[to]).unwrap();
+    }
+    if scripts.iter().any(|script| !script.enabled) {
+        writeln!(
+            mermaid,
+            "    classDef disabled stroke-dasharray: 5 5, color: #999"
+        )
+        .unwrap();
+    }
+    mermaid
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{disabled, script};
+
+    #[test]
+    fn test_render_dot() {
+        let build = script("build", &[]);
+        let test = disabled(script("test \"unit\"", &[]));
+
+        let dot = render_graph(&[&test, &build], &[(&build, &test)], GraphFormat::Dot);
+        assert_eq!(
+            dot,
+            [
+                "digraph rdo {",
+                "    \"build\";",
+                "    \"test \\\"unit\\\"\" [style=dashed, color=gray, fontcolor=gray];",
+                "    \"build\" -> \"test \\\"unit\\\"\";",
+                "}",
+                "",
+            ]
+            .join("\n")
+        );
+    }
+
+    #[test]
+    fn test_render_mermaid() {
+        let build = script("build", &[]);
+        let test = disabled(script("test \"unit\"", &[]));
+
+        let mermaid = render_graph(&[&test, &build], &[(&build, &test)], GraphFormat::Mermaid);
+        assert_eq!(
+            mermaid,
+            [
+                "graph TD",
+                "    s0[\"build\"]",
+                "    s1[\"test #quot;unit#quot;\"]:::disabled",
+                "    s0 --> s1",
+                "    classDef disabled stroke-dasharray: 5 5, color: #999",
+                "",
+            ]
+            .join("\n")
+        );
+    }
+}
@@ -0,0 +1,221 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::path::Path;
+use std::time::Duration;
+
+use crate::event::Event;
+use crate::output::OutputStream;
+use crate::summary::{ScriptReport, ScriptStatus};
+use crate::utils::error::Error;
+
+/// Collects the events of a run into a JUnit XML report, with one test case
+/// per script.
+#[derive(Debug, Default)]
+pub struct JunitReport {
+    scripts: Vec<String>,
+    reports: HashMap<String, ScriptReport>,
+    output: HashMap<(String, OutputStream), String>,
+    duration: Duration,
+}
+
+impl JunitReport {
+    pub fn new() -> JunitReport {
+        JunitReport::default()
+    }
+
+    pub fn record(&mut self, event: &Event) {
+        match event {
+            Event::RunStarted { scripts } => self.scripts = scripts.clone(),
+            Event::Output(output) => {
+                let captured = self
+                    .output
+                    .entry((output.script.clone(), output.stream))
+                    .or_default();
+                captured.push_str(&output.line);
+                captured.push('\n');
+            }
+            Event::ScriptFinished(report) => {
+                self.reports.insert(report.name.clone(), report.clone());
+            }
+            Event::RunFinished { duration, .. } => self.duration = *duration,
+            Event::ScriptQueued { .. } | Event::ScriptStarted { .. } => {}
+        }
+    }
+
+    pub fn to_xml(&self) -> String {
+        let reports = self
+            .scripts
+            .iter()
+            .filter_map(|name| self.reports.get(name))
+            .collect::<Vec<_>>();
+        let failures = reports.iter().filter(|r| is_failure(r.status)).count();
+        let skipped = reports.iter().filter(|r| is_skipped(r.status)).count();
+        let suite = format!(
+            "name=\"rdo\" tests=\"{}\" failures=\"{}\" errors=\"0\" skipped=\"{}\" time=\"{:.3}\"",
+            reports.len(),
+            failures,
+            skipped,
+            self.duration.as_secs_f64()
+        );
+
+        let mut xml = String::new();
+        writeln!(xml, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>").unwrap();
+        writeln!(xml, "<testsuites {}>", suite).unwrap();
+        writeln!(xml, "  <testsuite {}>", suite).unwrap();
+        for report in reports {
+            self.write_testcase(&mut xml, report);
+        }
+        writeln!(xml, "  </testsuite>").unwrap();
+        writeln!(xml, "</testsuites>").unwrap();
+        xml
+    }
+
+    pub fn write(&self, path: &Path) -> Result<(), Error> {
+        std::fs::write(path, self.to_xml())?;
+        Ok(())
+    }
+
+    fn write_testcase(&self, xml: &mut String, report: &ScriptReport) {
+        let time = report.duration.unwrap_or_default().as_secs_f64();
+        writeln!(
+            xml,
+            "    <testcase name=\"{}\" classname=\"rdo\" time=\"{:.3}\">",
+            escape(&report.name),
+            time
+        )
+        .unwrap();
+
+        let message = match (report.status, report.exit_code) {
+            (ScriptStatus::Failed, Some(code)) => format!("exited with code {}", code),
+            (status, _) => status.to_string(),
+        };
+        if is_failure(report.status) {
+            writeln!(
+                xml,
+                "      <failure message=\"{}\" type=\"{}\"/>",
+                escape(&message),
+                report.status
+            )
+            .unwrap();
+        } else if is_skipped(report.status) {
+            writeln!(xml, "      <skipped message=\"{}\"/>", escape(&message)).unwrap();
+        }
+
+        for (stream, element) in [
+            (OutputStream::Stdout, "system-out"),
+            (OutputStream::Stderr, "system-err"),
+        ] {
+            if let Some(output) = self.output.get(&(report.name.clone(), stream)) {
+                writeln!(xml, "      <{0}>{1}</{0}>", element, escape(output)).unwrap();
+            }
+        }
+        writeln!(xml, "    </testcase>").unwrap();
+    }
+}
+
+fn is_failure(status: ScriptStatus) -> bool {
+    matches!(status, ScriptStatus::Failed | ScriptStatus::TimedOut)
+}
+
+fn is_skipped(status: ScriptStatus) -> bool {
+    matches!(
+        status,
+        ScriptStatus::UpToDate
+            | ScriptStatus::Resumed
+            | ScriptStatus::Skipped
+            | ScriptStatus::Cancelled
+    )
+}
+
+/// Escapes `text` for use in XML content or attributes, dropping control
+/// characters (such as ANSI escapes) that XML 1.0 does not allow.
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            '\t' | '\n' | '\r' => escaped.push(c),
+            c if c.is_control() => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::OutputLine;
+
+    fn report(name: &str, status: ScriptStatus, exit_code: Option<i32>) -> ScriptReport {
+        ScriptReport {
+            name: name.to_string(),
+            status,
+            exit_code,
+            duration: Some(Duration::from_millis(250)),
+            attempts: 1,
+        }
+    }
+
+    #[test]
+    fn test_to_xml() {
+        let mut junit = JunitReport::new();
+        let events = [
+            Event::RunStarted {
+                scripts: vec![
+                    "build".to_string(),
+                    "test".to_string(),
+                    "deploy".to_string(),
+                ],
+            },
+            Event::Output(OutputLine::new(
+                "build",
+                OutputStream::Stdout,
+                "built <ok> & \x1b[32mdone\x1b[0m".to_string(),
+            )),
+            Event::Output(OutputLine::new(
+                "test",
+                OutputStream::Stderr,
+                "1 failed".to_string(),
+            )),
+            Event::ScriptFinished(report("test", ScriptStatus::Failed, Some(1))),
+            Event::ScriptFinished(report("build", ScriptStatus::Succeeded, Some(0))),
+            Event::ScriptFinished(ScriptReport::not_run("deploy", ScriptStatus::Skipped)),
+            Event::RunFinished {
+                success: false,
+                duration: Duration::from_millis(1500),
+                error: None,
+            },
+        ];
+        for event in &events {
+            junit.record(event);
+        }
+
+        let suite =
+            "name=\"rdo\" tests=\"3\" failures=\"1\" errors=\"0\" skipped=\"1\" time=\"1.500\"";
+        let expected = [
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>".to_string(),
+            format!("<testsuites {}>", suite),
+            format!("  <testsuite {}>", suite),
+            "    <testcase name=\"build\" classname=\"rdo\" time=\"0.250\">".to_string(),
+            "      <system-out>built &lt;ok&gt; &amp; [32mdone[0m".to_string(),
+            "</system-out>".to_string(),
+            "    </testcase>".to_string(),
+            "    <testcase name=\"test\" classname=\"rdo\" time=\"0.250\">".to_string(),
+            "      <failure message=\"exited with code 1\" type=\"failed\"/>".to_string(),
+            "      <system-err>1 failed".to_string(),
+            "</system-err>".to_string(),
+            "    </testcase>".to_string(),
+            "    <testcase name=\"deploy\" classname=\"rdo\" time=\"0.000\">".to_string(),
+            "      <skipped message=\"skipped\"/>".to_string(),
+            "    </testcase>".to_string(),
+            "  </testsuite>".to_string(),
+            "</testsuites>".to_string(),
+        ];
+        assert_eq!(junit.to_xml(), expected.join("\n") + "\n");
+    }
+}
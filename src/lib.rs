@@ -1,7 +1,18 @@
 #[macro_use]
 extern crate log;
 
+pub mod cache;
+pub mod event;
+pub mod executor;
+pub mod graph;
+pub mod junit;
+pub mod output;
+pub mod plan;
 pub mod resolver;
 pub mod runnable;
 pub mod script;
+pub mod state;
+pub mod summary;
+#[cfg(test)]
+pub(crate) mod test_support;
 pub mod utils;
@@ -1,18 +1,32 @@
+use std::path::Path;
 use std::process::exit;
 
 use clap::Parser;
-use log::error;
+use config::Config;
+use log::{error, info};
 use tokio::spawn;
-use tokio::sync::mpsc::Sender as MpscSender;
 use tokio::sync::watch::Receiver as WatchReceiver;
 use tokio::sync::{mpsc, watch};
 use tokio::task::spawn_blocking;
 
+use rdo::cache::{format_size, load_cache_from_config};
+use rdo::event::TimedEvent;
+use rdo::executor::{
+    load_executor_options_from_config, resolve_scripts, run_scripts_from_config, validate_jobs,
+    Selection,
+};
+use rdo::graph::{render_graph, GraphFormat};
+use rdo::junit::JunitReport;
+use rdo::output::{load_log_options_from_config, new_run_id, LogFiles, LogOptions};
+use rdo::plan::Plan;
 use rdo::resolver::Resolver;
-use rdo::runnable::Runnable;
 use rdo::script::load_all_scripts_from_config;
-use rdo::utils::cli::{handle_output, handle_signals, read_stdin, Cli, Commands};
-use rdo::utils::config::get_config_or_default;
+use rdo::state::{get_state_file, RunState};
+use rdo::utils::cli::{
+    handle_output, handle_signals, read_stdin, split_names, use_color, CacheCommands, Cli,
+    Commands, OutputFormat, RunArgs,
+};
+use rdo::utils::config::{get_config_dir, get_config_or_default};
 use rdo::utils::error::Error;
 use rdo::utils::logger::setup_logger;
 
@@ -21,14 +35,12 @@ async fn main() {
     let args = Cli::parse();
 
     let (stdin_tx, stdin_rx) = watch::channel::<String>(String::new());
-    let (stdout_tx, stdout_rx) = mpsc::channel::<String>(100);
+    let (cancel_tx, cancel_rx) = watch::channel(false);
 
     spawn_blocking(move || read_stdin(stdin_tx));
-    spawn(handle_signals());
-    spawn(handle_output(stdout_rx));
+    spawn(handle_signals(cancel_tx));
 
-    let result = handle_command(stdin_rx, stdout_tx, args).await;
-    match result {
+    match handle_command(stdin_rx, cancel_rx, args).await {
         Ok(_) => exit(0),
         Err(e) => {
             error!("Error: {}", e);
@@ -39,66 +51,209 @@ async fn main() {
 
 async fn handle_command(
     stdin_rx: WatchReceiver<String>,
-    stdout_tx: MpscSender<String>,
+    cancel_rx: WatchReceiver<bool>,
     args: Cli,
 ) -> Result<(), Error> {
     match args.command {
-        None => run(stdin_rx, stdout_tx, None, None).await,
+        None => run(stdin_rx, cancel_rx, RunArgs::default()).await,
         Some(command) => match command {
-            Commands::Run {
-                scripts,
-                config: config_path,
-                ..
-            } => run(stdin_rx, stdout_tx, scripts, config_path).await,
+            Commands::Run(run_args) => run(stdin_rx, cancel_rx, run_args).await,
             Commands::List {
                 config: config_path,
-            } => list(config_path),
+                dependents,
+            } => list(config_path, dependents),
+            Commands::Why {
+                target,
+                script,
+                config: config_path,
+            } => why(target, script, config_path),
+            Commands::Graph {
+                scripts,
+                config: config_path,
+                format,
+            } => graph(scripts, config_path, format),
+            Commands::Cache {
+                command:
+                    CacheCommands::Clean {
+                        config: config_path,
+                    },
+            } => clean_cache(config_path),
         },
     }
 }
 
 async fn run(
     stdin_rx: WatchReceiver<String>,
-    stdout_tx: MpscSender<String>,
-    maybe_script_names: Option<String>,
-    maybe_config_path: Option<String>,
+    cancel_rx: WatchReceiver<bool>,
+    args: RunArgs,
 ) -> Result<(), Error> {
-    let config = get_config_or_default(maybe_config_path)?;
+    let (config, config_path) = get_config_or_default(args.config.clone())?;
+    let config_dir = get_config_dir(&config_path)?;
     setup_logger(&config)?;
 
-    let scripts = load_all_scripts_from_config(&config)?;
+    let mut options = load_executor_options_from_config(&config)?;
+    if let Some(jobs) = args.jobs {
+        validate_jobs(jobs)?;
+        options.jobs = jobs;
+    }
+    if let Some(on_failure) = args.on_failure {
+        options.on_failure = on_failure;
+    }
+    if let Some(timeout) = args.timeout {
+        options.timeout = Some(timeout);
+    }
+    let state_file = get_state_file(&config_path)?;
+    if args.resume {
+        options.resume_from = Some(RunState::load(&state_file)?);
+    }
+    options.state_file = Some(state_file);
+    if !args.no_cache {
+        options.cache = Some(load_cache_from_config(&config, &config_dir)?);
+    }
+
+    let selection = args.selection();
+    if args.dry_run {
+        return plan(
+            &config,
+            &config_dir,
+            selection,
+            options.resume_from.as_ref(),
+        );
+    }
+
+    let mut log_options = load_log_options_from_config(&config, &config_dir)?;
+    if let Some(dir) = args.log_dir {
+        let separate_stderr = log_options.is_some_and(|o| o.separate_stderr);
+        log_options = Some(LogOptions {
+            dir,
+            separate_stderr,
+        });
+    }
+    if args.no_logs {
+        log_options = None;
+    }
+    let log_files = log_options.map(|log_options| LogFiles::new(&log_options, &new_run_id()));
+
+    let (output_tx, output_rx) = mpsc::channel::<TimedEvent>(100);
+    let output_handle = spawn(handle_output(
+        output_rx,
+        args.output,
+        use_color(args.no_color),
+        log_files,
+        args.junit.as_ref().map(|_| JunitReport::new()),
+    ));
+    let result = run_scripts_from_config(
+        &config,
+        &config_dir,
+        selection,
+        options,
+        stdin_rx,
+        output_tx,
+        cancel_rx,
+    )
+    .await;
+    // The sender is dropped once the run returns, so this waits for the
+    // remaining output to be printed before the summary.
+    let junit = output_handle.await.unwrap_or_default();
+
+    let summary = result?;
+    if let (Some(junit), Some(path)) = (junit, &args.junit) {
+        junit.write(path)?;
+        info!("Wrote JUnit report to {}", path.display());
+    }
+    if args.output == OutputFormat::Human {
+        println!("{}", summary);
+    }
+
+    match summary.error {
+        Some(e) => Err(e),
+        None => Ok(()),
+    }
+}
+
+fn plan(
+    config: &Config,
+    config_dir: &Path,
+    selection: Selection,
+    resume_from: Option<&RunState>,
+) -> Result<(), Error> {
+    let scripts = load_all_scripts_from_config(config, config_dir)?;
     let resolver = Resolver::new(scripts.iter().collect())?;
+    let mut plan = Plan::new(resolve_scripts(&resolver, selection)?);
+    plan.check_skipped(resume_from);
 
-    let sorted = match maybe_script_names {
-        Some(script_names) => {
-            let scripts_to_run = script_names
-                .split(',')
-                .map(|s| s.to_string())
-                .collect::<Vec<String>>();
-            resolver.resolve(scripts_to_run)?
-        }
-        None => resolver.resolve_all()?,
-    };
+    print!("{}", plan);
+    Ok(())
+}
 
-    for script in sorted {
-        if let Err(e) = script.run(stdin_rx.clone(), stdout_tx.clone()).await {
-            error!("Error running script {}: {}", script.name, e);
-            return Err(e);
-        }
+fn graph(
+    scripts: Option<String>,
+    config_path: Option<String>,
+    format: GraphFormat,
+) -> Result<(), Error> {
+    let (config, config_path) = get_config_or_default(config_path)?;
+    let config_dir = get_config_dir(&config_path)?;
+    setup_logger(&config)?;
+    let scripts_in_config = load_all_scripts_from_config(&config, &config_dir)?;
+    let resolver = Resolver::new(scripts_in_config.iter().collect())?;
+
+    let (scripts, edges) = resolver.subgraph(scripts.as_deref().map(split_names))?;
+    print!("{}", render_graph(&scripts, &edges, format));
+    Ok(())
+}
+
+fn why(target: String, script: String, config_path: Option<String>) -> Result<(), Error> {
+    let (config, config_path) = get_config_or_default(config_path)?;
+    let config_dir = get_config_dir(&config_path)?;
+    setup_logger(&config)?;
+    let scripts = load_all_scripts_from_config(&config, &config_dir)?;
+    let resolver = Resolver::new(scripts.iter().collect())?;
+
+    for path in resolver.explain(&target, &script)? {
+        let path = path.iter().map(|key| key.as_str()).collect::<Vec<_>>();
+        println!("{}", path.join(" -> "));
     }
     Ok(())
 }
 
-fn list(config_path: Option<String>) -> Result<(), Error> {
-    let config = get_config_or_default(config_path)?;
-    let scripts = load_all_scripts_from_config(&config)?;
-    let script_names = scripts
-        .iter()
-        .map(|s| s.name.clone())
-        .collect::<Vec<String>>()
-        .join(", ");
+fn clean_cache(config_path: Option<String>) -> Result<(), Error> {
+    let (config, config_path) = get_config_or_default(config_path)?;
+    let config_dir = get_config_dir(&config_path)?;
+    setup_logger(&config)?;
+    let cache = load_cache_from_config(&config, &config_dir)?;
+
+    let freed = cache.clean()?;
+    println!(
+        "Removed {} from {}",
+        format_size(freed),
+        cache.dir.display()
+    );
+    Ok(())
+}
+
+fn list(config_path: Option<String>, dependents: Option<String>) -> Result<(), Error> {
+    let (config, config_path) = get_config_or_default(config_path)?;
+    let all_scripts = load_all_scripts_from_config(&config, &get_config_dir(&config_path)?)?;
+    let mut scripts = match &dependents {
+        Some(names) => {
+            let resolver = Resolver::new(all_scripts.iter().collect())?;
+            resolver.find_dependents(split_names(names))?
+        }
+        None => all_scripts.iter().collect(),
+    };
+    scripts.sort_by(|a, b| a.name.cmp(&b.name));
 
-    println!("Available scripts: {}", script_names);
+    match &dependents {
+        Some(names) => println!("Scripts depending on {}:", names),
+        None => println!("Available scripts:"),
+    }
+    for script in scripts {
+        if script.enabled {
+            println!("  {}", script.name);
+        } else {
+            println!("  {} (disabled)", script.name);
+        }
+    }
     Ok(())
 }
 
@@ -124,12 +279,11 @@ mod tests {
         let cli = Cli::parse_from(args);
 
         let (stdin_tx, stdin_rx) = watch::channel::<String>(String::new());
-        let (stdout_tx, stdout_rx) = mpsc::channel::<String>(1);
+        let (_cancel_tx, cancel_rx) = watch::channel(false);
 
         select! {
             _ = read_stdin_async(stdin_tx) => {}
-            _ = handle_output(stdout_rx) => {}
-            result = handle_command(stdin_rx, stdout_tx, cli) => {
+            result = handle_command(stdin_rx, cancel_rx, cli) => {
                 assert!(result.is_ok());
                 exit(0);
             }
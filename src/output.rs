@@ -0,0 +1,277 @@
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use config::Config;
+use serde::{Deserialize, Serialize};
+
+use crate::utils::config::get_optional;
+use crate::utils::error::Error;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Hash)]
+#[serde(rename_all = "kebab-case")]
+pub enum OutputStream {
+    Stdout,
+    Stderr,
+}
+
+/// A single line written by a script.
+#[derive(Debug, Clone, Serialize)]
+pub struct OutputLine {
+    pub script: String,
+    pub stream: OutputStream,
+    #[serde(skip)]
+    pub timestamp: SystemTime,
+    pub line: String,
+}
+
+impl OutputLine {
+    pub fn new(script: &str, stream: OutputStream, line: String) -> OutputLine {
+        OutputLine {
+            script: script.to_string(),
+            stream,
+            timestamp: SystemTime::now(),
+            line,
+        }
+    }
+
+    /// Renders the line as `[script] line`. When `color` is an ANSI colour
+    /// code, the prefix is drawn in that colour and stderr lines in bold red.
+    pub fn render(&self, color: Option<&str>) -> String {
+        match color {
+            None => format!("[{}] {}", self.script, self.line),
+            Some(color) => match self.stream {
+                OutputStream::Stdout => {
+                    format!("\x1b[{}m[{}]\x1b[0m {}", color, self.script, self.line)
+                }
+                OutputStream::Stderr => format!(
+                    "\x1b[{}m[{}]\x1b[0m \x1b[1;31m{}\x1b[0m",
+                    color, self.script, self.line
+                ),
+            },
+        }
+    }
+}
+
+/// Directory, relative to the config file, that logs are written to unless
+/// `log.dir` is set.
+const DEFAULT_LOG_DIR: &str = "logs";
+
+/// Where to keep per-script log files, read from the `[log]` section.
+#[derive(Debug, Clone)]
+pub struct LogOptions {
+    pub dir: PathBuf,
+    /// Write stderr to `<script>.stderr` instead of interleaving it in `<script>.log`.
+    pub separate_stderr: bool,
+}
+
+/// Returns `None` if `log.enabled` is false. `log.dir` defaults to `logs`,
+/// and is resolved against the config directory if relative.
+pub fn load_log_options_from_config(
+    config: &Config,
+    config_dir: &Path,
+) -> Result<Option<LogOptions>, Error> {
+    if !get_optional(config, "log.enabled")?.unwrap_or(true) {
+        return Ok(None);
+    }
+    let dir = get_optional::<String>(config, "log.dir")?.unwrap_or_else(|| DEFAULT_LOG_DIR.into());
+    let dir = config_dir.join(dir);
+    let separate_stderr = get_optional(config, "log.separate_stderr")?.unwrap_or(false);
+
+    Ok(Some(LogOptions {
+        dir,
+        separate_stderr,
+    }))
+}
+
+/// A run id that sorts by start time and is safe to use as a directory name.
+pub fn new_run_id() -> String {
+    humantime::format_rfc3339_millis(SystemTime::now())
+        .to_string()
+        .replace(':', "-")
+}
+
+/// The log files of a single run, kept in `<dir>/<run-id>/`. The directory
+/// is only created once a script writes its first line.
+pub struct LogFiles {
+    dir: PathBuf,
+    separate_stderr: bool,
+    files: HashMap<(String, OutputStream), File>,
+}
+
+impl LogFiles {
+    pub fn new(options: &LogOptions, run_id: &str) -> LogFiles {
+        LogFiles {
+            dir: options.dir.join(run_id),
+            separate_stderr: options.separate_stderr,
+            files: HashMap::new(),
+        }
+    }
+
+    /// Appends `output` to its script's log file, creating the file on first use.
+    pub fn write(&mut self, output: &OutputLine) -> Result<(), Error> {
+        let stream = match output.stream {
+            OutputStream::Stderr if self.separate_stderr => OutputStream::Stderr,
+            _ => OutputStream::Stdout,
+        };
+
+        let first_file = self.files.is_empty();
+        let file = match self.files.entry((output.script.clone(), stream)) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                if first_file {
+                    std::fs::create_dir_all(&self.dir)?;
+                    info!("Writing script logs to {}", self.dir.display());
+                }
+                let extension = match stream {
+                    OutputStream::Stdout => "log",
+                    OutputStream::Stderr => "stderr",
+                };
+                let file_name = format!("{}.{}", log_file_stem(&output.script), extension);
+                let path = self.dir.join(file_name);
+                entry.insert(OpenOptions::new().create(true).append(true).open(path)?)
+            }
+        };
+
+        writeln!(file, "{}", output.line)?;
+        Ok(())
+    }
+}
+
+/// The script name with anything but ASCII letters, digits, `-`, `_` and `.`
+/// replaced by `_`, so that its log files stay inside the run directory.
+fn log_file_stem(script: &str) -> String {
+    let stem = script
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || "-_.".contains(c) {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect::<String>();
+    if stem.is_empty() || stem.starts_with('.') {
+        format!("_{}", stem)
+    } else {
+        stem
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{config, TempDir};
+
+    #[test]
+    fn test_render_without_color() {
+        let output = OutputLine::new("build", OutputStream::Stderr, "oops".to_string());
+        assert_eq!(output.render(None), "[build] oops");
+    }
+
+    fn log_options(dir: &TempDir, separate_stderr: bool) -> LogOptions {
+        LogOptions {
+            dir: dir.join("logs"),
+            separate_stderr,
+        }
+    }
+
+    fn write_lines(log_files: &mut LogFiles) {
+        for (script, stream, line) in [
+            ("build", OutputStream::Stdout, "compiling"),
+            ("test", OutputStream::Stdout, "running"),
+            ("build", OutputStream::Stderr, "warning"),
+            ("build", OutputStream::Stdout, "done"),
+        ] {
+            let output = OutputLine::new(script, stream, line.to_string());
+            log_files.write(&output).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_log_files_interleave_stderr() {
+        let dir = TempDir::new("logs");
+        let options = log_options(&dir, false);
+        let mut log_files = LogFiles::new(&options, "run");
+        write_lines(&mut log_files);
+
+        let run_dir = options.dir.join("run");
+        let build = std::fs::read_to_string(run_dir.join("build.log")).unwrap();
+        let test = std::fs::read_to_string(run_dir.join("test.log")).unwrap();
+        let has_stderr = run_dir.join("build.stderr").exists();
+
+        assert_eq!(build, "compiling\nwarning\ndone\n");
+        assert_eq!(test, "running\n");
+        assert!(!has_stderr);
+    }
+
+    #[test]
+    fn test_log_files_separate_stderr() {
+        let dir = TempDir::new("logs-stderr");
+        let options = log_options(&dir, true);
+        let mut log_files = LogFiles::new(&options, "run");
+        write_lines(&mut log_files);
+
+        let run_dir = options.dir.join("run");
+        let stdout = std::fs::read_to_string(run_dir.join("build.log")).unwrap();
+        let stderr = std::fs::read_to_string(run_dir.join("build.stderr")).unwrap();
+
+        assert_eq!(stdout, "compiling\ndone\n");
+        assert_eq!(stderr, "warning\n");
+    }
+
+    #[test]
+    fn test_log_dir_is_created_on_first_write() {
+        let dir = TempDir::new("logs-lazy");
+        let options = log_options(&dir, false);
+        let mut log_files = LogFiles::new(&options, "run");
+        let created_early = options.dir.exists();
+        let output = OutputLine::new("../../escape", OutputStream::Stdout, "line".to_string());
+        log_files.write(&output).unwrap();
+
+        let files = std::fs::read_dir(options.dir.join("run"))
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect::<Vec<_>>();
+
+        assert!(!created_early);
+        assert_eq!(files, ["_.._.._escape.log"]);
+    }
+
+    #[test]
+    fn test_log_file_stem() {
+        assert_eq!(log_file_stem("build-1.2_x"), "build-1.2_x");
+        assert_eq!(log_file_stem("a/b c"), "a_b_c");
+        assert_eq!(log_file_stem(".."), "_..");
+        assert_eq!(log_file_stem(""), "_");
+    }
+
+    #[test]
+    fn test_logs_are_written_by_default() {
+        let load = |toml: &str| {
+            load_log_options_from_config(&config(toml), Path::new("/project")).unwrap()
+        };
+
+        let default = load("").unwrap();
+        assert_eq!(default.dir, Path::new("/project/logs"));
+        assert!(!default.separate_stderr);
+        let custom = load("[log]\ndir = \"out/logs\"").unwrap();
+        assert_eq!(custom.dir, Path::new("/project/out/logs"));
+        assert!(load("[log]\nenabled = false").is_none());
+    }
+
+    #[test]
+    fn test_render_with_color() {
+        let stdout = OutputLine::new("build", OutputStream::Stdout, "ok".to_string());
+        assert_eq!(stdout.render(Some("36")), "\x1b[36m[build]\x1b[0m ok");
+
+        let stderr = OutputLine::new("build", OutputStream::Stderr, "oops".to_string());
+        assert_eq!(
+            stderr.render(Some("36")),
+            "\x1b[36m[build]\x1b[0m \x1b[1;31moops\x1b[0m"
+        );
+    }
+}
@@ -0,0 +1,200 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt::{Display, Formatter};
+
+use crate::executor::reason_to_skip;
+use crate::script::Script;
+use crate::state::RunState;
+use crate::summary::ScriptStatus;
+
+/// The order in which a run would execute its scripts, grouped into stages.
+/// All scripts in a stage can run in parallel once the previous stages are done.
+pub struct Plan<'a> {
+    pub stages: Vec<Vec<&'a Script>>,
+    /// Scripts the run would skip, with the status it would report for them.
+    pub skipped: HashMap<&'a str, ScriptStatus>,
+}
+
+impl<'a> Plan<'a> {
+    /// Groups `scripts` into stages. They are expected in the same order as
+    /// [`Executor::new`](crate::executor::Executor::new) takes them.
+    pub fn new(scripts: Vec<&'a Script>) -> Plan<'a> {
+        let mut stage_of = HashMap::new();
+        let mut stages: Vec<Vec<&'a Script>> = Vec::new();
+
+        for script in scripts {
+            let stage = script
+                .dependencies
+                .iter()
+                .filter_map(|dep| stage_of.get(dep.as_str()))
+                .map(|stage| stage + 1)
+                .max()
+                .unwrap_or(0);
+            stage_of.insert(script.name.as_str(), stage);
+
+            if stages.len() <= stage {
+                stages.resize_with(stage + 1, Vec::new);
+            }
+            stages[stage].push(script);
+        }
+
+        Plan {
+            stages,
+            skipped: HashMap::new(),
+        }
+    }
+
+    /// Walks the stages in order, asking [`reason_to_skip`] about each script.
+    /// Scripts it returns a status for are recorded in `skipped`; the others
+    /// count as run for the scripts that depend on them.
+    pub fn check_skipped(&mut self, resume_from: Option<&RunState>) {
+        let mut ran = HashSet::new();
+        for script in self.stages.iter().flatten() {
+            match reason_to_skip(script, resume_from, &ran) {
+                Some(status) => {
+                    self.skipped.insert(script.name.as_str(), status);
+                }
+                None => {
+                    ran.insert(script.name.clone());
+                }
+            }
+        }
+    }
+}
+
+impl Display for Plan<'_> {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        for (i, stage) in self.stages.iter().enumerate() {
+            writeln!(f, "Stage {}:", i + 1)?;
+            for script in stage {
+                if let Some(status) = self.skipped.get(script.name.as_str()) {
+                    writeln!(f, "  {} ({}, would not run)", script.name, status)?;
+                    continue;
+                }
+
+                let command = script.command();
+                let command_line = std::iter::once(command.get_program())
+                    .chain(command.get_args())
+                    .map(|arg| shell_quote(&arg.to_string_lossy()))
+                    .collect::<Vec<_>>()
+                    .join(" ");
+
+                writeln!(f, "  {}", script.name)?;
+                writeln!(f, "    command: {}", command_line)?;
+                if let Some(cwd) = &script.cwd {
+                    writeln!(f, "    cwd: {}", cwd.display())?;
+                }
+                if !script.env.is_empty() {
+                    let env = script
+                        .env
+                        .iter()
+                        .map(|(key, value)| format!("{}={}", key, shell_quote(value)))
+                        .collect::<Vec<_>>()
+                        .join(" ");
+                    writeln!(f, "    env: {}", env)?;
+                }
+                if !script.inherit_env {
+                    writeln!(f, "    inherit_env: false")?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Quotes `arg` for a POSIX shell if it contains anything but plain characters.
+fn shell_quote(arg: &str) -> String {
+    let is_plain = |c: char| c.is_ascii_alphanumeric() || "-_./=:,@%+".contains(c);
+    if !arg.is_empty() && arg.chars().all(is_plain) {
+        arg.to_string()
+    } else {
+        format!("'{}'", arg.replace('\'', "'\\''"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::test_support::script;
+
+    #[test]
+    fn test_stages() {
+        let scripts = [
+            script("a", &[]),
+            script("b", &[]),
+            script("c", &["a"]),
+            script("d", &["b", "c"]),
+            script("e", &["a", "outside"]),
+        ];
+        let plan = Plan::new(scripts.iter().collect());
+
+        let stages = plan
+            .stages
+            .iter()
+            .map(|stage| stage.iter().map(|s| s.name.as_str()).collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        assert_eq!(stages, [vec!["a", "b"], vec!["c", "e"], vec!["d"]]);
+    }
+
+    #[test]
+    fn test_display() {
+        let mut build = script("build", &[]);
+        build.args = vec!["--release".to_string()];
+        build.cwd = Some(PathBuf::from("/src"));
+        build
+            .env
+            .insert("GREETING".to_string(), "hello world".to_string());
+        build.inherit_env = false;
+        let test = script("test", &["build"]);
+
+        let plan = Plan::new(vec![&build, &test]);
+        assert_eq!(
+            plan.to_string(),
+            [
+                "Stage 1:",
+                "  build",
+                "    command: bash -c 'echo build' -- --release",
+                "    cwd: /src",
+                "    env: GREETING='hello world'",
+                "    inherit_env: false",
+                "Stage 2:",
+                "  test",
+                "    command: bash -c 'echo test' --",
+                "",
+            ]
+            .join("\n")
+        );
+    }
+
+    #[test]
+    fn test_skipped_scripts_are_marked() {
+        let mut state = RunState::default();
+        state
+            .scripts
+            .insert("build".to_string(), ScriptStatus::Succeeded);
+        state
+            .scripts
+            .insert("test".to_string(), ScriptStatus::Succeeded);
+        let build = script("build", &[]);
+        let lint = script("lint", &[]);
+        let test = script("test", &["build", "lint"]);
+
+        let mut plan = Plan::new(vec![&build, &lint, &test]);
+        plan.check_skipped(Some(&state));
+        assert_eq!(
+            plan.to_string(),
+            [
+                "Stage 1:",
+                "  build (resumed, would not run)",
+                "  lint",
+                "    command: bash -c 'echo lint' --",
+                "Stage 2:",
+                "  test",
+                "    command: bash -c 'echo test' --",
+                "",
+            ]
+            .join("\n")
+        );
+    }
+}
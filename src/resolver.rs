@@ -1,4 +1,4 @@
-use std::fmt::Debug;
+use std::fmt::{Debug, Display};
 use std::hash::Hash;
 
 use crate::utils::error::Error;
@@ -15,27 +15,220 @@ where
 impl<'a, T, K> Resolver<'a, T, K>
 where
     T: GraphLike<'a, K> + Debug + 'a,
-    K: Eq + Hash + Debug + 'a,
+    K: Eq + Hash + Debug + Display + 'a,
 {
     pub fn new(nodes: Vec<&'a T>) -> Result<Resolver<'a, T, K>, Error> {
         let graph_binding = GraphBinding::new(nodes)?;
         Ok(Resolver { graph_binding })
     }
 
-    pub fn resolve(&'a self, keys: Vec<K>) -> Result<Vec<&T>, Error> {
+    /// Resolves the given scripts and their dependencies. Fails if any of them
+    /// is disabled.
+    pub fn resolve(&'a self, keys: Vec<K>) -> Result<Vec<&'a T>, Error> {
         let nodes = self.graph_binding.find_nodes_by_keys(keys)?;
+        if let Some(node) = nodes.iter().find(|node| !node.is_enabled()) {
+            return Err(Error::ScriptDisabled(node.get_key().to_string()));
+        }
         self.resolve_nodes(nodes)
     }
 
-    pub fn resolve_all(&'a self) -> Result<Vec<&T>, Error> {
-        let nodes = self.graph_binding.get_all_nodes();
+    /// Resolves all enabled scripts. Fails if an enabled script depends on a
+    /// disabled one.
+    pub fn resolve_all(&'a self) -> Result<Vec<&'a T>, Error> {
+        let nodes = self
+            .graph_binding
+            .get_all_nodes()
+            .into_iter()
+            .filter(|node| node.is_enabled())
+            .collect();
         self.resolve_nodes(nodes)
     }
 
-    fn resolve_nodes(&'a self, nodes: Vec<&'a T>) -> Result<Vec<&T>, Error> {
+    /// Returns the given scripts and everything they depend on, or all scripts
+    /// if `keys` is `None`, with the `(dependency, dependent)` edges between
+    /// them. Unlike `resolve`, disabled scripts are included.
+    #[allow(clippy::type_complexity)]
+    pub fn subgraph(
+        &self,
+        keys: Option<Vec<K>>,
+    ) -> Result<(Vec<&'a T>, Vec<(&'a T, &'a T)>), Error> {
+        let nodes = match keys {
+            Some(keys) => Some(self.graph_binding.find_nodes_by_keys(keys)?),
+            None => None,
+        };
+        self.graph_binding.get_subgraph(nodes)
+    }
+
+    /// Explains why `key` is part of a run of `target`: returns every path of
+    /// dependencies leading from `target` to `key`.
+    pub fn explain(&self, target: &K, key: &K) -> Result<Vec<Vec<&'a K>>, Error> {
+        let paths = self.graph_binding.find_dependency_paths(target, key)?;
+        if paths.is_empty() {
+            return Err(Error::NotADependency(target.to_string(), key.to_string()));
+        }
+        Ok(paths)
+    }
+
+    /// Resolves the given scripts and every enabled script that depends on
+    /// them, without the other dependencies of those scripts. Fails if any of
+    /// the given scripts is disabled.
+    pub fn resolve_dependents(&'a self, keys: Vec<K>) -> Result<Vec<&'a T>, Error> {
+        let nodes = self.graph_binding.find_nodes_by_keys(keys)?;
+        if let Some(node) = nodes.iter().find(|node| !node.is_enabled()) {
+            return Err(Error::ScriptDisabled(node.get_key().to_string()));
+        }
+
+        let dependents = self
+            .graph_binding
+            .get_dependents(nodes)?
+            .into_iter()
+            .filter(|node| node.is_enabled())
+            .collect();
+        let sorted = self.graph_binding.sort(dependents)?.collect();
+        self.check_dependencies_enabled(sorted)
+    }
+
+    /// Returns every script that depends on one of the given scripts, directly
+    /// or transitively, including disabled ones.
+    pub fn find_dependents(&self, keys: Vec<K>) -> Result<Vec<&'a T>, Error> {
+        let nodes = self.graph_binding.find_nodes_by_keys(keys)?;
+        let given = nodes.iter().map(|node| node.get_key()).collect::<Vec<_>>();
         Ok(self
+            .graph_binding
+            .get_dependents(nodes)?
+            .into_iter()
+            .filter(|node| !given.contains(&node.get_key()))
+            .collect())
+    }
+
+    /// Keeps the script `key` and those of `nodes` that depend on it, in their
+    /// original order. Fails if `key` is not one of `nodes`.
+    pub fn filter_dependents_of(&self, nodes: Vec<&'a T>, key: &K) -> Result<Vec<&'a T>, Error> {
+        let node = self.graph_binding.find_node_by_key(key)?;
+        if !nodes.iter().any(|n| n.get_key() == key) {
+            return Err(Error::ScriptNotSelected(key.to_string()));
+        }
+
+        let dependents = self
+            .graph_binding
+            .get_dependents(vec![node])?
+            .into_iter()
+            .map(|n| n.get_key())
+            .collect::<Vec<_>>();
+        Ok(nodes
+            .into_iter()
+            .filter(|n| dependents.contains(&n.get_key()))
+            .collect())
+    }
+
+    pub fn find(&self, key: &K) -> Result<&'a T, Error> {
+        self.graph_binding.find_node_by_key(key)
+    }
+
+    fn resolve_nodes(&'a self, nodes: Vec<&'a T>) -> Result<Vec<&'a T>, Error> {
+        let sorted = self
             .graph_binding
             .topological_sort(nodes)
-            .collect::<Vec<&T>>())
+            .collect::<Vec<&T>>();
+        self.check_dependencies_enabled(sorted)
+    }
+
+    fn check_dependencies_enabled(&self, sorted: Vec<&'a T>) -> Result<Vec<&'a T>, Error> {
+        for node in sorted.iter() {
+            for child_key in node.get_children_keys() {
+                let child = self.graph_binding.find_node_by_key(child_key)?;
+                if !child.is_enabled() {
+                    return Err(Error::ScriptDependencyDisabled(
+                        node.get_key().to_string(),
+                        child_key.to_string(),
+                    ));
+                }
+            }
+        }
+
+        Ok(sorted)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::script::Script;
+    use crate::test_support::{disabled, script};
+
+    #[test]
+    fn test_resolve_all_excludes_disabled_scripts() {
+        let scripts = [script("a", &[]), disabled(script("b", &[]))];
+        let resolver = Resolver::new(scripts.iter().collect()).unwrap();
+
+        let names = resolver
+            .resolve_all()
+            .unwrap()
+            .iter()
+            .map(|s| s.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["a"]);
+    }
+
+    #[test]
+    fn test_resolve_dependents() {
+        let scripts = [
+            script("base", &[]),
+            script("other", &[]),
+            script("middle", &["base", "other"]),
+            script("top", &["middle"]),
+            disabled(script("off", &["base"])),
+        ];
+        let resolver = Resolver::new(scripts.iter().collect()).unwrap();
+
+        let names =
+            |scripts: Vec<&Script>| scripts.iter().map(|s| s.name.clone()).collect::<Vec<_>>();
+        let resolved = resolver.resolve_dependents(vec!["base".to_string()]);
+        assert_eq!(names(resolved.unwrap()), ["base", "middle", "top"]);
+
+        let mut dependents = names(resolver.find_dependents(vec!["base".to_string()]).unwrap());
+        dependents.sort();
+        assert_eq!(dependents, ["middle", "off", "top"]);
+    }
+
+    #[test]
+    fn test_filter_dependents_of() {
+        let scripts = [
+            script("fetch", &[]),
+            script("build", &["fetch"]),
+            script("lint", &["fetch"]),
+            script("test", &["build"]),
+            script("unused", &["build"]),
+        ];
+        let resolver = Resolver::new(scripts.iter().collect()).unwrap();
+        let resolved = resolver
+            .resolve(vec!["test".to_string(), "lint".to_string()])
+            .unwrap();
+
+        let filtered = resolver
+            .filter_dependents_of(resolved.clone(), &"build".to_string())
+            .unwrap();
+        let names = filtered.iter().map(|s| s.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, ["build", "test"]);
+
+        assert!(matches!(
+            resolver.filter_dependents_of(resolved, &"unused".to_string()),
+            Err(Error::ScriptNotSelected(script)) if script == "unused"
+        ));
+    }
+
+    #[test]
+    fn test_disabled_dependency_is_an_error() {
+        let scripts = [script("a", &["b"]), disabled(script("b", &[]))];
+        let resolver = Resolver::new(scripts.iter().collect()).unwrap();
+
+        assert!(matches!(
+            resolver.resolve_all(),
+            Err(Error::ScriptDependencyDisabled(script, dep)) if script == "a" && dep == "b"
+        ));
+        assert!(matches!(
+            resolver.resolve(vec!["b".to_string()]),
+            Err(Error::ScriptDisabled(script)) if script == "b"
+        ));
     }
 }
@@ -1,3 +1,4 @@
+use crate::event::TimedEvent;
 use crate::utils::error::Error;
 use async_trait::async_trait;
 use tokio::sync::mpsc::Sender;
@@ -5,9 +6,25 @@ use tokio::sync::watch::Receiver as WatchReceiver;
 
 #[async_trait]
 pub trait Runnable {
+    /// Runs to completion, or until `cancel_rx` is set to `true`.
+    ///
+    /// Sending to `output_tx` waits while the channel is full, so its receiver
+    /// must be drained for the run to make progress, or dropped to discard the
+    /// events.
     async fn run(
         &self,
         stdin_rx: WatchReceiver<String>,
-        output_tx: Sender<String>,
+        output_tx: Sender<TimedEvent>,
+        cancel_rx: WatchReceiver<bool>,
     ) -> Result<(), Error>;
 }
+
+/// Resolves once `cancel_rx` is set to `true`. Never resolves if the sender is
+/// dropped without cancelling.
+pub async fn wait_for_cancel(cancel_rx: &mut WatchReceiver<bool>) {
+    while !*cancel_rx.borrow_and_update() {
+        if cancel_rx.changed().await.is_err() {
+            std::future::pending::<()>().await;
+        }
+    }
+}
@@ -1,34 +1,124 @@
-use std::io::Read;
-use std::os::unix::prelude::PermissionsExt;
+use std::collections::{BTreeMap, BTreeSet};
+use std::os::unix::prelude::{CommandExt, ExitStatusExt, PermissionsExt};
+use std::path::{Path, PathBuf};
 use std::process::Stdio;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime};
 
 use async_trait::async_trait;
 use config::Config;
 use serde::{Deserialize, Serialize};
-use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines};
-use tokio::process::{ChildStderr, ChildStdin, ChildStdout, Command};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncWriteExt, BufReader, Lines};
+use tokio::process::{Child, ChildStderr, ChildStdin, ChildStdout, Command};
 use tokio::sync::mpsc::Sender;
+use tokio::sync::watch;
 use tokio::sync::watch::Receiver as WatchReceiver;
 
-use crate::runnable::Runnable;
+use crate::event::{Event, TimedEvent};
+use crate::executor::FailurePolicy;
+use crate::output::{OutputLine, OutputStream};
+use crate::runnable::{wait_for_cancel, Runnable};
+use crate::utils::config::{get_optional, get_optional_duration};
 use crate::utils::error::Error;
+use crate::utils::files::{expand_globs, modified};
 use crate::utils::graph_binding::GraphLike;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Hash, Default)]
+const DEFAULT_RETRY_DELAY: Duration = Duration::from_secs(1);
+const DEFAULT_GRACE_PERIOD: Duration = Duration::from_secs(10);
+/// How long to keep reading a script's output after it exited. Background
+/// processes it started can hold the pipes open for much longer.
+const OUTPUT_DRAIN_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Process groups of the scripts that are running, so that they can still be
+/// killed when `rdo` exits without waiting for them.
+static PROCESS_GROUPS: Mutex<BTreeSet<u32>> = Mutex::new(BTreeSet::new());
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Hash, Default)]
 pub enum ScriptType {
     #[default]
     Bash,
+    Python,
+    Node,
+    Perl,
+    Ruby,
+    /// Runs `interpreter`, followed by `args`, the script and the script's
+    /// arguments.
+    Custom {
+        interpreter: String,
+        #[serde(default)]
+        args: Vec<String>,
+    },
+}
+
+impl ScriptType {
+    /// Builds the command that runs `code` with `args` using this interpreter.
+    pub fn command(&self, code: &str, args: &[String]) -> std::process::Command {
+        let mut command = match self {
+            ScriptType::Bash => interpreter_command("bash", &["-c", code, "--"]),
+            ScriptType::Python => interpreter_command("python3", &["-c", code]),
+            ScriptType::Node => interpreter_command("node", &["-e", code, "--"]),
+            ScriptType::Perl => interpreter_command("perl", &["-e", code, "--"]),
+            ScriptType::Ruby => interpreter_command("ruby", &["-e", code, "--"]),
+            ScriptType::Custom {
+                interpreter,
+                args: interpreter_args,
+            } => {
+                let mut command = std::process::Command::new(interpreter);
+                command.args(interpreter_args).arg(code);
+                command
+            }
+        };
+        command.args(args);
+        command
+    }
+}
+
+fn interpreter_command(program: &str, args: &[&str]) -> std::process::Command {
+    let mut command = std::process::Command::new(program);
+    command.args(args);
+    command
+}
+
+/// How the delay between retries grows with each failed attempt.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Hash, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum RetryBackoff {
+    #[default]
+    Fixed,
+    Exponential,
 }
 
 #[derive(Debug, Clone)]
 pub struct Script {
     pub name: String,
-    pub cmd: String,
+    /// Inline command, run by the interpreter for `script_type`.
+    pub cmd: Option<String>,
+    /// Executable file, run directly so that its shebang is honoured.
     pub path: Option<String>,
     pub script_type: ScriptType,
     pub args: Vec<String>,
     pub dependencies: Vec<String>,
     pub enabled: bool,
+    pub on_failure: Option<FailurePolicy>,
+    pub retries: u32,
+    pub retry_delay: Duration,
+    pub retry_backoff: RetryBackoff,
+    pub timeout: Option<Duration>,
+    pub grace_period: Duration,
+    /// Variables set for the script, on top of the inherited environment.
+    pub env: BTreeMap<String, String>,
+    /// When false, only `PATH` is inherited.
+    pub inherit_env: bool,
+    /// Working directory, or the directory `rdo` was started in if unset.
+    pub cwd: Option<PathBuf>,
+    /// Glob patterns of the files the script reads, or `None` if they are
+    /// not declared.
+    pub inputs: Option<Vec<String>>,
+    /// Glob patterns of the files the script writes.
+    pub outputs: Vec<String>,
+    /// Whether the script's outputs and stdout can be restored from the
+    /// result cache instead of running it.
+    pub cache: bool,
 }
 
 impl Script {
@@ -40,35 +130,31 @@ impl Script {
         args: Vec<String>,
         dependencies: Vec<String>,
         enabled: bool,
-    ) -> Script {
-        if (path.is_none() && cmd.is_none()) || (path.is_some() && cmd.is_some()) {
-            panic!("Exactly one of path, cmd must be set");
+    ) -> Result<Script, Error> {
+        if path.is_some() == cmd.is_some() {
+            return Err(Error::InvalidScript(
+                name.to_string(),
+                "exactly one of path, cmd must be set".to_string(),
+            ));
         }
 
-        let mut cmd = cmd.unwrap_or_default();
         let mut path = path;
 
-        if path.is_some() {
-            path = Option::from(
-                std::fs::canonicalize(path.clone().unwrap())
-                    .unwrap()
-                    .to_str()
-                    .unwrap()
-                    .to_string(),
-            );
+        if let Some(script_path) = path {
+            let canonical = std::fs::canonicalize(&script_path)
+                .map_err(|_| Error::ScriptFileNotFound(name.to_string(), script_path.clone()))?;
+            path = Some(canonical.to_string_lossy().to_string());
 
             if !is_file(&path) {
-                panic!("{} is not a file", path.unwrap());
+                return Err(Error::ScriptFileNotFound(name.to_string(), script_path));
             }
 
             if !is_executable(&path) {
-                panic!("{} is not executable", path.unwrap());
+                return Err(Error::ScriptNotExecutable(name.to_string(), script_path));
             }
-
-            cmd = load_script_file(&path).unwrap();
         }
 
-        Script {
+        Ok(Script {
             name: name.to_string(),
             cmd,
             path,
@@ -76,15 +162,100 @@ impl Script {
             args,
             dependencies,
             enabled,
+            on_failure: None,
+            retries: 0,
+            retry_delay: DEFAULT_RETRY_DELAY,
+            retry_backoff: RetryBackoff::default(),
+            timeout: None,
+            grace_period: DEFAULT_GRACE_PERIOD,
+            env: BTreeMap::new(),
+            inherit_env: true,
+            cwd: None,
+            inputs: None,
+            outputs: vec![],
+            cache: false,
+        })
+    }
+
+    /// Builds the command that runs this script. Files are executed directly
+    /// and read at run time; inline commands go through their interpreter.
+    pub fn command(&self) -> std::process::Command {
+        let mut command = match &self.path {
+            Some(path) => {
+                let mut command = std::process::Command::new(path);
+                command.args(&self.args);
+                command
+            }
+            None => self
+                .script_type
+                .command(self.cmd.as_deref().unwrap_or_default(), &self.args),
+        };
+
+        if !self.inherit_env {
+            command.env_clear();
+            // Without PATH, commands such as `make` would not be found.
+            if let Some(path) = std::env::var_os("PATH") {
+                command.env("PATH", path);
+            }
+        }
+        command.envs(&self.env);
+        if let Some(cwd) = &self.cwd {
+            command.current_dir(cwd);
         }
+        command
     }
-}
 
-fn load_script_file(path: &Option<String>) -> Result<String, Error> {
-    let mut file = std::fs::File::open(path.clone().unwrap())?;
-    let mut contents = String::new();
-    file.read_to_string(&mut contents)?;
-    Ok(contents)
+    /// Whether every output pattern matches at least one file and all of
+    /// those files are newer than the script's inputs. Scripts without
+    /// outputs or without declared inputs are never up to date, nor are
+    /// scripts whose inputs match no files. `inputs = []` makes the outputs
+    /// alone decide.
+    pub fn outputs_are_fresh(&self) -> Result<bool, Error> {
+        let Some(inputs) = &self.inputs else {
+            return Ok(false);
+        };
+        if self.outputs.is_empty() {
+            return Ok(false);
+        }
+
+        let mut oldest_output = None;
+        for pattern in &self.outputs {
+            let outputs = expand_globs(std::slice::from_ref(pattern))?;
+            if outputs.is_empty() {
+                return Ok(false);
+            }
+            for output in outputs {
+                let modified = modified(&output)?;
+                oldest_output =
+                    Some(oldest_output.map_or(modified, |m: SystemTime| m.min(modified)));
+            }
+        }
+
+        let mut newest_input = None;
+        for input in expand_globs(inputs)? {
+            let modified = modified(&input)?;
+            newest_input = Some(newest_input.map_or(modified, |m: SystemTime| m.max(modified)));
+        }
+
+        Ok(match (oldest_output, newest_input) {
+            (Some(output), Some(input)) => output > input,
+            (Some(_), None) if inputs.is_empty() => true,
+            _ => {
+                warn!("Inputs of script {} match no files", self.name);
+                false
+            }
+        })
+    }
+
+    /// Delay before retrying after the given failed attempt (starting at 1).
+    pub fn retry_delay_for(&self, attempt: u32) -> Duration {
+        match self.retry_backoff {
+            RetryBackoff::Fixed => self.retry_delay,
+            RetryBackoff::Exponential => self
+                .retry_delay
+                .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1))),
+        }
+    }
 }
 
 #[async_trait]
@@ -92,54 +263,193 @@ impl Runnable for Script {
     async fn run(
         &self,
         mut stdin_rx: WatchReceiver<String>,
-        output_tx: Sender<String>,
+        output_tx: Sender<TimedEvent>,
+        mut cancel_rx: WatchReceiver<bool>,
     ) -> Result<(), Error> {
         info!("Starting script: {}", self.name);
-        let mut child = Command::new("sh")
-            .arg("-c")
-            .arg(&self.cmd)
-            .arg("--")
-            .args(&self.args)
+        if let Some(cwd) = self.cwd.as_ref().filter(|cwd| !cwd.is_dir()) {
+            return Err(Error::InvalidScript(
+                self.name.clone(),
+                format!("cwd {} is not a directory", cwd.display()),
+            ));
+        }
+        let mut command = self.command();
+        command.process_group(0);
+        let mut child = Command::from(command)
             .stdin(Stdio::piped())
             .stdout(Stdio::piped())
             .stderr(Stdio::piped())
+            .kill_on_drop(true)
             .spawn()?;
 
         let mut stdout = BufReader::new(child.stdout.take().unwrap()).lines();
         let mut stderr = BufReader::new(child.stderr.take().unwrap()).lines();
         let stdin = child.stdin.take().unwrap();
+        let pid = child.id();
+        let _process_group = pid.map(ProcessGroup::register);
+        let (exited_tx, exited_rx) = watch::channel(false);
+
+        let outcome = {
+            let completion = async {
+                let wait = async {
+                    let status = child.wait().await;
+                    exited_tx.send_replace(true);
+                    status
+                };
+                let io = handle_io(
+                    &self.name,
+                    &mut stdin_rx,
+                    stdin,
+                    &mut stdout,
+                    &mut stderr,
+                    output_tx,
+                    exited_rx,
+                );
+                tokio::join!(io, wait)
+            };
 
-        tokio::select! {
-            e = handle_io(&mut stdin_rx, stdin, &mut stdout, &mut stderr, output_tx) => {
-                e
+            tokio::select! {
+                (result, status) = completion => Ok((result, status?)),
+                _ = sleep_for_timeout(self.timeout) => {
+                    Err(Error::ScriptTimedOut(self.name.clone(), self.timeout.unwrap_or_default()))
+                }
+                _ = wait_for_cancel(&mut cancel_rx) => Err(Error::ScriptCancelled(self.name.clone())),
             }
-            _ = child.wait() => {
-                debug!("Script {} finished", self.name);
-                Ok(())
+        };
+
+        let (result, status) = match outcome {
+            Ok(outcome) => outcome,
+            Err(e) => {
+                warn!("{}, terminating", e);
+                terminate(&mut child, pid, self.grace_period).await?;
+                return Err(e);
             }
+        };
+
+        debug!("Script {} finished: {}", self.name, status);
+        result?;
+
+        if !status.success() {
+            return Err(Error::ScriptFailed(
+                self.name.clone(),
+                status.code(),
+                status.signal(),
+            ));
         }
+
+        Ok(())
+    }
+}
+
+async fn sleep_for_timeout(timeout: Option<Duration>) {
+    match timeout {
+        Some(timeout) => tokio::time::sleep(timeout).await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Sends SIGTERM to the script's process group, then SIGKILL if it is still
+/// running after `grace_period`.
+async fn terminate(
+    child: &mut Child,
+    pid: Option<u32>,
+    grace_period: Duration,
+) -> Result<(), Error> {
+    let Some(pid) = pid else {
+        return Ok(());
+    };
+
+    signal_process_group(pid, libc::SIGTERM);
+    if tokio::time::timeout(grace_period, child.wait())
+        .await
+        .is_err()
+    {
+        warn!(
+            "Process group {} still running after {}, killing",
+            pid,
+            humantime::format_duration(grace_period)
+        );
+        signal_process_group(pid, libc::SIGKILL);
+        child.wait().await?;
+    }
+
+    Ok(())
+}
+
+/// Keeps a script's process group in `PROCESS_GROUPS` while the script runs.
+struct ProcessGroup(u32);
+
+impl ProcessGroup {
+    fn register(pgid: u32) -> ProcessGroup {
+        PROCESS_GROUPS.lock().unwrap().insert(pgid);
+        ProcessGroup(pgid)
+    }
+}
+
+impl Drop for ProcessGroup {
+    fn drop(&mut self) {
+        PROCESS_GROUPS.lock().unwrap().remove(&self.0);
+    }
+}
+
+/// Sends SIGKILL to the process group of every script that is running. Used
+/// before exiting, as scripts do not get the terminal's SIGINT themselves.
+pub fn kill_running_scripts() {
+    for &pgid in PROCESS_GROUPS.lock().unwrap().iter() {
+        signal_process_group(pgid, libc::SIGKILL);
+    }
+}
+
+fn signal_process_group(pid: u32, signal: libc::c_int) {
+    // SAFETY: killpg has no memory safety requirements.
+    let result = unsafe { libc::killpg(pid as libc::pid_t, signal) };
+    if result != 0 {
+        debug!(
+            "Could not signal process group {}: {}",
+            pid,
+            std::io::Error::last_os_error()
+        );
     }
 }
 
 /// Pass stdin into the script and return stdout/stderr through output_tx.
+/// Returns once both stdout and stderr have been read, see `forward_lines`.
 async fn handle_io(
+    script: &str,
     stdin_rx: &mut WatchReceiver<String>,
     stdin: ChildStdin,
     stdout: &mut Lines<BufReader<ChildStdout>>,
     stderr: &mut Lines<BufReader<ChildStderr>>,
-    output_tx: Sender<String>,
+    output_tx: Sender<TimedEvent>,
+    exited_rx: WatchReceiver<bool>,
 ) -> Result<(), Error> {
+    let output = async {
+        tokio::try_join!(
+            forward_lines(
+                stdout,
+                script,
+                OutputStream::Stdout,
+                &output_tx,
+                exited_rx.clone()
+            ),
+            forward_lines(stderr, script, OutputStream::Stderr, &output_tx, exited_rx)
+        )
+        .map(|_| ())
+    };
+    tokio::pin!(output);
+
     tokio::select! {
-        r = handle_stdin(stdin_rx, stdin) => {
-            r
-        }
-        r = handle_stdout(stdout, &output_tx) => {
-            r
+        r = &mut output => {
+            return r;
         }
-        r = handle_stderr(stderr, &output_tx) => {
-            r
+        r = handle_stdin(stdin_rx, stdin) => {
+            if let Err(e) = r {
+                debug!("Stopped forwarding stdin: {}", e);
+            }
         }
     }
+
+    output.await
 }
 
 async fn handle_stdin(
@@ -148,12 +458,8 @@ async fn handle_stdin(
 ) -> Result<(), Error> {
     loop {
         let changed = stdin_rx.changed().await;
-        match changed {
-            Ok(_) => {}
-            Err(e) => {
-                error!("Script stdin closed: {}", e);
-                return Err(Error::StdinClosed);
-            }
+        if changed.is_err() {
+            return Err(Error::StdinClosed);
         }
 
         let line = stdin_rx.borrow().clone();
@@ -164,44 +470,48 @@ async fn handle_stdin(
     }
 }
 
-async fn handle_stdout(
-    stdout: &mut Lines<BufReader<ChildStdout>>,
-    output_tx: &Sender<String>,
+/// Sends each line read from a script's stdout or stderr through output_tx
+/// until the stream is closed. Once `exited_rx` is set, it stops after at
+/// most `OUTPUT_DRAIN_TIMEOUT` spent waiting for the rest.
+async fn forward_lines<R: AsyncBufRead + Unpin>(
+    lines: &mut Lines<R>,
+    script: &str,
+    stream: OutputStream,
+    output_tx: &Sender<TimedEvent>,
+    mut exited_rx: WatchReceiver<bool>,
 ) -> Result<(), Error> {
+    let mut drain_left = OUTPUT_DRAIN_TIMEOUT;
     loop {
-        let line = stdout.next_line().await?;
-
-        match line {
-            Some(line) => {
-                output_tx
-                    .send_timeout(line, std::time::Duration::from_millis(100))
-                    .await
-                    .unwrap_or_else(|e| {
-                        error!("Script stdout send error: {}", e);
-                    });
+        let line = if *exited_rx.borrow() {
+            let started = Instant::now();
+            match tokio::time::timeout(drain_left, lines.next_line()).await {
+                Ok(line) => {
+                    drain_left = drain_left.saturating_sub(started.elapsed());
+                    line?
+                }
+                Err(_) => {
+                    debug!(
+                        "Script {} exited but its {:?} is still open, not waiting for it",
+                        script, stream
+                    );
+                    return Ok(());
+                }
             }
-            None => {
-                return Ok(());
+        } else {
+            tokio::select! {
+                line = lines.next_line() => line?,
+                _ = exited_rx.changed() => continue,
             }
-        }
-    }
-}
-
-async fn handle_stderr(
-    stderr: &mut Lines<BufReader<ChildStderr>>,
-    output_tx: &Sender<String>,
-) -> Result<(), Error> {
-    loop {
-        let line = stderr.next_line().await?;
+        };
 
         match line {
             Some(line) => {
-                output_tx
-                    .send_timeout(line, std::time::Duration::from_millis(100))
-                    .await
-                    .unwrap_or_else(|e| {
-                        error!("Script stderr send error: {}", e);
-                    });
+                // Wait for a slow consumer rather than dropping lines: every
+                // line has to reach the log files and reports.
+                let event = TimedEvent::new(Event::Output(OutputLine::new(script, stream, line)));
+                if output_tx.send(event).await.is_err() {
+                    debug!("Dropped output of script {}: output channel closed", script);
+                }
             }
             None => {
                 return Ok(());
@@ -220,16 +530,27 @@ fn is_executable(path: &Option<String>) -> bool {
         .unwrap_or(false)
 }
 
-pub fn load_script_from_config(name: &str, config: &Config) -> Result<Script, Error> {
+/// Loads the script `name`. Relative paths in it are resolved against
+/// `config_dir`, the directory containing the config file.
+pub fn load_script_from_config(
+    name: &str,
+    config: &Config,
+    config_dir: &Path,
+) -> Result<Script, Error> {
     let path = config
         .get::<Option<String>>(&format!("script.{}.path", name))
-        .unwrap_or_default();
+        .unwrap_or_default()
+        .map(|path| config_dir.join(path).to_string_lossy().to_string());
     let cmd = config
         .get::<Option<String>>(&format!("script.{}.cmd", name))
         .unwrap_or_default();
-    let script_type = config
-        .get::<Option<ScriptType>>(&format!("script.{}.type", name))
-        .unwrap_or_default();
+    let script_type = get_optional::<ScriptType>(config, &format!("script.{}.type", name))?;
+    if let (Some(_), Some(script_type)) = (&path, &script_type) {
+        warn!(
+            "Script {} is executed from its path, so its type {:?} is ignored",
+            name, script_type
+        );
+    }
 
     let args = config
         .get_array(&format!("script.{}.args", name))
@@ -245,9 +566,7 @@ pub fn load_script_from_config(name: &str, config: &Config) -> Result<Script, Er
         .map(|s| s.to_string())
         .collect::<Vec<String>>();
 
-    let enabled = config
-        .get_bool(&format!("script.{}.enabled", name))
-        .unwrap_or_default();
+    let enabled = get_optional(config, &format!("script.{}.enabled", name))?.unwrap_or(true);
 
     debug!(
         "Loaded script: {} ({}), type: {:?}, args: {:?}, dependencies: {:?}",
@@ -257,35 +576,138 @@ pub fn load_script_from_config(name: &str, config: &Config) -> Result<Script, Er
         args,
         dependencies
     );
-    Ok(Script::new(
-        name,
-        cmd,
-        path,
-        script_type,
-        args,
-        dependencies,
-        enabled,
-    ))
+    let mut script = Script::new(name, cmd, path, script_type, args, dependencies, enabled)?;
+    script.on_failure = get_optional(config, &format!("script.{}.on_failure", name))?;
+    if let Some(retries) = get_optional(config, &format!("script.{}.retries", name))? {
+        script.retries = retries;
+    }
+    if let Some(delay) = get_optional_duration(config, &format!("script.{}.retry_delay", name))? {
+        script.retry_delay = delay;
+    }
+    if let Some(backoff) = get_optional(config, &format!("script.{}.retry_backoff", name))? {
+        script.retry_backoff = backoff;
+    }
+    script.timeout = get_optional_duration(config, &format!("script.{}.timeout", name))?;
+    if let Some(grace_period) =
+        get_optional_duration(config, &format!("script.{}.grace_period", name))?
+    {
+        script.grace_period = grace_period;
+    }
+    if let Some(inherit_env) = get_optional(config, &format!("script.{}.inherit_env", name))? {
+        script.inherit_env = inherit_env;
+    }
+    script.env = load_env_from_config(name, config, config_dir)?;
+    let cwd = load_cwd_from_config(name, config, config_dir)?;
+    script.inputs = load_globs_from_config(name, "inputs", &cwd, config)?;
+    script.outputs = load_globs_from_config(name, "outputs", &cwd, config)?.unwrap_or_default();
+    script.cwd = Some(cwd);
+    if let Some(cache) = get_optional(config, &format!("script.{}.cache", name))? {
+        script.cache = cache;
+    }
+    Ok(script)
+}
+
+/// Reads a list of glob patterns, if given, and makes them relative to the
+/// script's `cwd`.
+fn load_globs_from_config(
+    name: &str,
+    key: &str,
+    cwd: &Path,
+    config: &Config,
+) -> Result<Option<Vec<String>>, Error> {
+    let Some(patterns) = get_optional::<Vec<String>>(config, &format!("script.{}.{}", name, key))?
+    else {
+        return Ok(None);
+    };
+
+    patterns
+        .iter()
+        .map(|pattern| {
+            let pattern = if Path::new(pattern).is_absolute() {
+                pattern.clone()
+            } else {
+                let cwd = glob::Pattern::escape(&cwd.to_string_lossy());
+                format!("{}/{}", cwd.trim_end_matches('/'), pattern)
+            };
+            match glob::Pattern::new(&pattern) {
+                Ok(_) => Ok(pattern),
+                Err(err) => Err(Error::InvalidScript(
+                    name.to_string(),
+                    format!("invalid {} pattern {}: {}", key, pattern, err),
+                )),
+            }
+        })
+        .collect::<Result<_, _>>()
+        .map(Some)
+}
+
+/// Resolves the script's `cwd` against the config directory, which is also
+/// the default. Whether it exists is only checked when the script runs.
+fn load_cwd_from_config(name: &str, config: &Config, config_dir: &Path) -> Result<PathBuf, Error> {
+    Ok(
+        match get_optional::<String>(config, &format!("script.{}.cwd", name))? {
+            Some(cwd) => config_dir.join(cwd),
+            None => config_dir.to_path_buf(),
+        },
+    )
+}
+
+/// Merges the global `[env]` table, the script's `env_file` and its `env`
+/// table, with later sources taking precedence.
+fn load_env_from_config(
+    name: &str,
+    config: &Config,
+    config_dir: &Path,
+) -> Result<BTreeMap<String, String>, Error> {
+    let mut env = get_optional::<BTreeMap<String, String>>(config, "env")?.unwrap_or_default();
+
+    if let Some(env_file) = get_optional::<String>(config, &format!("script.{}.env_file", name))? {
+        let env_file = config_dir.join(env_file);
+        let entries = dotenvy::from_path_iter(&env_file).map_err(|e| {
+            Error::InvalidScript(
+                name.to_string(),
+                format!("cannot read {}: {}", env_file.display(), e),
+            )
+        })?;
+        for entry in entries {
+            let (key, value) = entry.map_err(|e| {
+                Error::InvalidScript(
+                    name.to_string(),
+                    format!("cannot parse {}: {}", env_file.display(), e),
+                )
+            })?;
+            env.insert(key, value);
+        }
+    }
+
+    let script_env =
+        get_optional::<BTreeMap<String, String>>(config, &format!("script.{}.env", name))?;
+    env.extend(script_env.unwrap_or_default());
+    Ok(env)
 }
 
 pub fn load_scripts_from_config(
     config: &Config,
+    config_dir: &Path,
     scripts_to_add: Vec<String>,
 ) -> Result<Vec<Script>, Error> {
     let mut scripts = Vec::new();
     for (name, _) in config.get_table("script")? {
         if scripts_to_add.contains(&name) {
-            scripts.push(load_script_from_config(&name, config)?);
+            scripts.push(load_script_from_config(&name, config, config_dir)?);
         }
     }
     Ok(scripts)
 }
 
-pub fn load_all_scripts_from_config(config: &Config) -> Result<Vec<Script>, Error> {
+pub fn load_all_scripts_from_config(
+    config: &Config,
+    config_dir: &Path,
+) -> Result<Vec<Script>, Error> {
     config
         .get_table("script")?
         .keys()
-        .map(|name| load_script_from_config(name.as_str(), config))
+        .map(|name| load_script_from_config(name.as_str(), config, config_dir))
         .collect()
 }
 
@@ -297,4 +719,335 @@ impl<'a> GraphLike<'a, String> for Script {
     fn get_children_keys(&'a self) -> Vec<&'a String> {
         self.dependencies.iter().collect()
     }
+
+    fn is_enabled(&'a self) -> bool {
+        self.enabled
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{cmd_script, config, TempDir};
+    use crate::utils::config::{get_config_dir, get_config_from_file};
+
+    async fn next_line(output_rx: &mut tokio::sync::mpsc::Receiver<TimedEvent>) -> String {
+        loop {
+            if let Event::Output(output) = output_rx.recv().await.unwrap().event {
+                return output.line;
+            }
+        }
+    }
+
+    fn command_line(script_type: ScriptType) -> Vec<String> {
+        let command = script_type.command("code", &["a".to_string(), "-b".to_string()]);
+        std::iter::once(command.get_program())
+            .chain(command.get_args())
+            .map(|arg| arg.to_string_lossy().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_script_types_pass_args_to_interpreter() {
+        assert_eq!(
+            command_line(ScriptType::Bash),
+            vec!["bash", "-c", "code", "--", "a", "-b"]
+        );
+        assert_eq!(
+            command_line(ScriptType::Python),
+            vec!["python3", "-c", "code", "a", "-b"]
+        );
+        assert_eq!(
+            command_line(ScriptType::Node),
+            vec!["node", "-e", "code", "--", "a", "-b"]
+        );
+        assert_eq!(
+            command_line(ScriptType::Custom {
+                interpreter: "fish".to_string(),
+                args: vec!["--no-config".to_string(), "-c".to_string()],
+            }),
+            vec!["fish", "--no-config", "-c", "code", "a", "-b"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_python_script_runs() {
+        let has_python = std::process::Command::new("python3")
+            .arg("--version")
+            .output()
+            .is_ok_and(|output| output.status.success());
+        if !has_python {
+            eprintln!("python3 is not installed, skipping");
+            return;
+        }
+
+        let mut script = cmd_script("python", "import sys; print('python', sys.argv[1])", &[]);
+        script.script_type = ScriptType::Python;
+        script.args = vec!["arg".to_string()];
+
+        let (_stdin_tx, stdin_rx) = tokio::sync::watch::channel(String::new());
+        let (_cancel_tx, cancel_rx) = tokio::sync::watch::channel(false);
+        let (output_tx, mut output_rx) = tokio::sync::mpsc::channel(10);
+        assert!(script.run(stdin_rx, output_tx, cancel_rx).await.is_ok());
+        assert_eq!(next_line(&mut output_rx).await, "python arg");
+    }
+
+    #[test]
+    fn test_custom_type_is_loaded_from_config() {
+        let config = config(
+            r#"
+            [script.deno]
+            cmd = "console.log(1)"
+            type = { Custom = { interpreter = "deno", args = ["eval"] } }
+            "#,
+        );
+
+        let script = load_script_from_config("deno", &config, Path::new(".")).unwrap();
+        assert_eq!(
+            script.script_type,
+            ScriptType::Custom {
+                interpreter: "deno".to_string(),
+                args: vec!["eval".to_string()],
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_path_script_is_executed_directly() {
+        let dir = TempDir::new("exec");
+        let path = dir.join("script.sh");
+        std::fs::write(&path, "#!/bin/sh\necho \"$0 $1\"\n").unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let script = Script::new(
+            "exec",
+            None,
+            Some(path.to_string_lossy().to_string()),
+            None,
+            vec!["arg".to_string()],
+            vec![],
+            true,
+        )
+        .unwrap();
+        // Edits made after loading the config are picked up.
+        std::fs::write(&path, "#!/bin/sh\necho \"edited $0 $1\"\n").unwrap();
+
+        let (_stdin_tx, stdin_rx) = tokio::sync::watch::channel(String::new());
+        let (_cancel_tx, cancel_rx) = tokio::sync::watch::channel(false);
+        let (output_tx, mut output_rx) = tokio::sync::mpsc::channel(10);
+        let result = script.run(stdin_rx, output_tx, cancel_rx).await;
+        let line = next_line(&mut output_rx).await;
+
+        assert!(result.is_ok());
+        assert_eq!(line, format!("edited {} arg", script.path.unwrap()));
+    }
+
+    #[tokio::test]
+    async fn test_env_precedence_and_isolation() {
+        let dir = TempDir::new("env");
+        let env_file = dir.join("vars.env");
+        std::fs::write(&env_file, "FROM_FILE=file\nOVERRIDDEN=file\n").unwrap();
+        let config = config(&format!(
+            r#"
+            [env]
+            GLOBAL = "global"
+            FROM_FILE = "global"
+
+            [script.hermetic]
+            cmd = "echo $GLOBAL $FROM_FILE $OVERRIDDEN ${{HOME:-unset}} ${{PATH:+path}}"
+            env_file = "{}"
+            env = {{ OVERRIDDEN = "inline" }}
+            inherit_env = false
+            "#,
+            env_file.display()
+        ));
+
+        let script = load_script_from_config("hermetic", &config, Path::new(".")).unwrap();
+
+        let (_stdin_tx, stdin_rx) = tokio::sync::watch::channel(String::new());
+        let (_cancel_tx, cancel_rx) = tokio::sync::watch::channel(false);
+        let (output_tx, mut output_rx) = tokio::sync::mpsc::channel(10);
+        assert!(script.run(stdin_rx, output_tx, cancel_rx).await.is_ok());
+        assert_eq!(
+            next_line(&mut output_rx).await,
+            "global file inline unset path"
+        );
+    }
+
+    #[test]
+    fn test_outputs_are_fresh() {
+        let dir = TempDir::new("fresh");
+        std::fs::create_dir(dir.join("src")).unwrap();
+        let touch = |name: &str, age: u64| {
+            let file = std::fs::File::create(dir.join(name)).unwrap();
+            let modified = SystemTime::now() - Duration::from_secs(age);
+            file.set_modified(modified).unwrap();
+        };
+        touch("src/a.txt", 30);
+        touch("src/b.txt", 20);
+        touch("out.bin", 10);
+
+        let config = config(
+            r#"
+            [script.build]
+            cmd = "true"
+            inputs = ["src/*.txt"]
+            outputs = ["out.bin"]
+
+            [script.missing]
+            cmd = "true"
+            inputs = ["src/*.txt"]
+            outputs = ["out.bin", "missing.bin"]
+
+            [script.undeclared]
+            cmd = "true"
+            outputs = ["out.bin"]
+
+            [script.unmatched]
+            cmd = "true"
+            inputs = ["lib/*.txt"]
+            outputs = ["out.bin"]
+
+            [script.outputs_only]
+            cmd = "true"
+            inputs = []
+            outputs = ["out.bin"]
+            "#,
+        );
+        let build = load_script_from_config("build", &config, &dir).unwrap();
+        let missing = load_script_from_config("missing", &config, &dir).unwrap();
+        let fresh_when = |name: &str| {
+            load_script_from_config(name, &config, &dir)
+                .unwrap()
+                .outputs_are_fresh()
+                .unwrap()
+        };
+
+        let fresh = build.outputs_are_fresh().unwrap();
+        let missing_fresh = missing.outputs_are_fresh().unwrap();
+        let undeclared_fresh = fresh_when("undeclared");
+        let unmatched_fresh = fresh_when("unmatched");
+        let outputs_only_fresh = fresh_when("outputs_only");
+        touch("src/b.txt", 0);
+        let stale = build.outputs_are_fresh().unwrap();
+
+        assert!(fresh);
+        assert!(!missing_fresh);
+        assert!(!undeclared_fresh);
+        assert!(!unmatched_fresh);
+        assert!(outputs_only_fresh);
+        assert!(!stale);
+    }
+
+    #[test]
+    fn test_paths_are_relative_to_config_dir() {
+        let dir = TempDir::new("cwd");
+        std::fs::create_dir(dir.join("sub")).unwrap();
+        let script_path = dir.join("run.sh");
+        std::fs::write(&script_path, "#!/bin/sh\npwd\n").unwrap();
+        std::fs::set_permissions(&script_path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        std::fs::write(dir.join("vars.env"), "GREETING=hello\n").unwrap();
+        let config_path = dir.join("config.toml");
+        std::fs::write(
+            &config_path,
+            [
+                "[script.default]\ncmd = \"pwd\"\n",
+                "[script.sub]\ncmd = \"pwd\"\ncwd = \"sub\"\n",
+                "[script.file]\npath = \"run.sh\"\nenv_file = \"vars.env\"\n",
+            ]
+            .join("\n"),
+        )
+        .unwrap();
+
+        let config = get_config_from_file(&config_path.to_string_lossy()).unwrap();
+        let config_dir = get_config_dir(&config_path).unwrap();
+        let default = load_script_from_config("default", &config, &config_dir).unwrap();
+        let sub = load_script_from_config("sub", &config, &config_dir).unwrap();
+        let file = load_script_from_config("file", &config, &config_dir).unwrap();
+        let dir = dir.canonicalize().unwrap();
+
+        assert_eq!(default.cwd, Some(dir.clone()));
+        assert_eq!(sub.cwd, Some(dir.join("sub")));
+        assert_eq!(
+            file.path,
+            Some(dir.join("run.sh").to_string_lossy().to_string())
+        );
+        assert_eq!(file.env["GREETING"], "hello");
+    }
+
+    #[tokio::test]
+    async fn test_missing_cwd_fails_when_run() {
+        let config = config("[script.gone]\ncmd = \"true\"\ncwd = \"missing\"\n");
+        let dir = TempDir::new("gone");
+        let script = load_script_from_config("gone", &config, &dir).unwrap();
+
+        let (_stdin_tx, stdin_rx) = tokio::sync::watch::channel(String::new());
+        let (_cancel_tx, cancel_rx) = tokio::sync::watch::channel(false);
+        let (output_tx, _output_rx) = tokio::sync::mpsc::channel(10);
+        assert!(matches!(
+            script.run(stdin_rx, output_tx, cancel_rx).await,
+            Err(Error::InvalidScript(name, _)) if name == "gone"
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_background_process_does_not_hold_up_the_script() {
+        let script = cmd_script("daemon", "sleep 30 & echo $!", &[]);
+
+        let (_stdin_tx, stdin_rx) = tokio::sync::watch::channel(String::new());
+        let (_cancel_tx, cancel_rx) = tokio::sync::watch::channel(false);
+        let (output_tx, mut output_rx) = tokio::sync::mpsc::channel(10);
+        // The background process keeps stdout open for 30 seconds, so without
+        // a bound on draining the output this would not return in time.
+        let result = tokio::time::timeout(
+            Duration::from_secs(10),
+            script.run(stdin_rx, output_tx, cancel_rx),
+        )
+        .await;
+        let pid = next_line(&mut output_rx).await;
+        std::process::Command::new("kill")
+            .args(["-9", &pid])
+            .status()
+            .unwrap();
+
+        assert!(result.expect("script did not return").is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_running_process_groups_are_tracked() {
+        let script = cmd_script("tracked", "echo $$; sleep 30", &[]);
+
+        let (_stdin_tx, stdin_rx) = tokio::sync::watch::channel(String::new());
+        let (cancel_tx, cancel_rx) = tokio::sync::watch::channel(false);
+        let (output_tx, mut output_rx) = tokio::sync::mpsc::channel(10);
+        let run = tokio::spawn(async move { script.run(stdin_rx, output_tx, cancel_rx).await });
+        let pgid = next_line(&mut output_rx).await.parse::<u32>().unwrap();
+        let tracked = PROCESS_GROUPS.lock().unwrap().contains(&pgid);
+        cancel_tx.send_replace(true);
+        let result = run.await.unwrap();
+
+        assert!(tracked);
+        assert!(matches!(result, Err(Error::ScriptCancelled(_))));
+        assert!(!PROCESS_GROUPS.lock().unwrap().contains(&pgid));
+    }
+
+    #[tokio::test]
+    async fn test_slow_consumer_receives_every_line() {
+        let script = cmd_script("chatty", "seq 200", &[]);
+
+        let (_stdin_tx, stdin_rx) = tokio::sync::watch::channel(String::new());
+        let (_cancel_tx, cancel_rx) = tokio::sync::watch::channel(false);
+        let (output_tx, mut output_rx) = tokio::sync::mpsc::channel(1);
+        let run = tokio::spawn(async move { script.run(stdin_rx, output_tx, cancel_rx).await });
+        tokio::time::sleep(Duration::from_millis(300)).await;
+
+        let mut lines = 0;
+        while let Some(event) = output_rx.recv().await {
+            if matches!(event.event, Event::Output(_)) {
+                lines += 1;
+            }
+        }
+        assert!(run.await.unwrap().is_ok());
+        assert_eq!(lines, 200);
+    }
 }
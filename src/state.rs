@@ -0,0 +1,99 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::summary::ScriptStatus;
+use crate::utils::config::{get_config_dir, get_config_name, get_state_dir};
+use crate::utils::error::Error;
+
+/// The outcome of each script in the last run of a config, kept so that a
+/// later run can resume where it left off.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RunState {
+    pub scripts: BTreeMap<String, ScriptStatus>,
+}
+
+impl RunState {
+    /// Loads the state at `path`, or an empty state if there is none yet.
+    pub fn load(path: &Path) -> Result<RunState, Error> {
+        match std::fs::read_to_string(path) {
+            Ok(contents) => serde_json::from_str(&contents).map_err(|err| {
+                Error::Unspecified(format!("invalid run state {}: {}", path.display(), err))
+            }),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(RunState::default()),
+            Err(err) => Err(Error::Io(err)),
+        }
+    }
+
+    /// Writes the state to `path`, replacing it atomically so an interrupted
+    /// run never leaves a truncated file behind.
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        let contents = serde_json::to_string_pretty(self)
+            .map_err(|err| Error::Unspecified(err.to_string()))?;
+        // Each process writes its own temporary file, so that concurrent runs
+        // of the same config never rename each other's half-written state.
+        let tmp_path = path.with_extension(format!("{}.tmp", std::process::id()));
+        std::fs::write(&tmp_path, contents)?;
+        std::fs::rename(&tmp_path, path)?;
+        Ok(())
+    }
+
+    /// Whether the script's last outcome lets a resumed run skip it.
+    pub fn is_done(&self, script: &str) -> bool {
+        matches!(
+            self.scripts.get(script),
+            Some(
+                ScriptStatus::Succeeded
+                    | ScriptStatus::UpToDate
+                    | ScriptStatus::Cached
+                    | ScriptStatus::Resumed
+            )
+        )
+    }
+}
+
+/// Where the run state of the config at `config_path` is kept, e.g.
+/// `.rdo/config.state.json` next to the config file.
+pub fn get_state_file(config_path: &Path) -> Result<PathBuf, Error> {
+    let state_dir = get_state_dir(&get_config_dir(config_path)?);
+    Ok(state_dir.join(format!("{}.state.json", get_config_name(config_path))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TempDir;
+
+    #[test]
+    fn test_save_and_load() {
+        let dir = TempDir::new("state");
+        let path = dir.join("config.state.json");
+        assert_eq!(RunState::load(&path).unwrap(), RunState::default());
+
+        let mut state = RunState::default();
+        state
+            .scripts
+            .insert("build".to_string(), ScriptStatus::Succeeded);
+        state
+            .scripts
+            .insert("test".to_string(), ScriptStatus::Failed);
+        state.save(&path).unwrap();
+        let loaded = RunState::load(&path).unwrap();
+        let files = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect::<Vec<_>>();
+
+        assert_eq!(files, ["config.state.json"]);
+
+        assert_eq!(loaded, state);
+        assert!(loaded.is_done("build"));
+        assert!(!loaded.is_done("test"));
+        assert!(!loaded.is_done("deploy"));
+    }
+}
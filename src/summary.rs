@@ -0,0 +1,157 @@
+use std::fmt::{Display, Formatter};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::event::serialize_optional_duration;
+use crate::utils::error::Error;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Hash)]
+#[serde(rename_all = "kebab-case")]
+pub enum ScriptStatus {
+    Succeeded,
+    /// Not run because its last successful run is still valid.
+    UpToDate,
+    /// Not run because its outputs and output were restored from the cache.
+    Cached,
+    /// Not run because it succeeded in the run being resumed.
+    Resumed,
+    Failed,
+    /// Not run because one of its dependencies did not succeed.
+    Skipped,
+    TimedOut,
+    /// Terminated, or never started, because the run was stopped.
+    Cancelled,
+}
+
+impl Display for ScriptStatus {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        match self {
+            ScriptStatus::Succeeded => write!(f, "succeeded"),
+            ScriptStatus::UpToDate => write!(f, "up to date"),
+            ScriptStatus::Cached => write!(f, "cached"),
+            ScriptStatus::Resumed => write!(f, "resumed"),
+            ScriptStatus::Failed => write!(f, "failed"),
+            ScriptStatus::Skipped => write!(f, "skipped"),
+            ScriptStatus::TimedOut => write!(f, "timed out"),
+            ScriptStatus::Cancelled => write!(f, "cancelled"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ScriptReport {
+    #[serde(rename = "script")]
+    pub name: String,
+    pub status: ScriptStatus,
+    pub exit_code: Option<i32>,
+    /// Total time spent running the script, including retries.
+    #[serde(serialize_with = "serialize_optional_duration")]
+    pub duration: Option<Duration>,
+    pub attempts: u32,
+}
+
+impl ScriptReport {
+    pub fn not_run(name: &str, status: ScriptStatus) -> ScriptReport {
+        ScriptReport {
+            name: name.to_string(),
+            status,
+            exit_code: None,
+            duration: None,
+            attempts: 0,
+        }
+    }
+
+    pub fn from_result(
+        name: &str,
+        result: &Result<(), Error>,
+        duration: Duration,
+        attempts: u32,
+    ) -> ScriptReport {
+        let (status, exit_code) = match result {
+            Ok(()) => (ScriptStatus::Succeeded, Some(0)),
+            Err(Error::ScriptFailed(_, code, _)) => (ScriptStatus::Failed, *code),
+            Err(Error::ScriptTimedOut(..)) => (ScriptStatus::TimedOut, None),
+            Err(Error::ScriptCancelled(_)) => (ScriptStatus::Cancelled, None),
+            Err(_) => (ScriptStatus::Failed, None),
+        };
+
+        ScriptReport {
+            name: name.to_string(),
+            status,
+            exit_code,
+            duration: Some(duration),
+            attempts,
+        }
+    }
+}
+
+/// The outcome of a run, with one report per script in resolved order.
+#[derive(Debug)]
+pub struct RunSummary {
+    pub scripts: Vec<ScriptReport>,
+    pub duration: Duration,
+    /// The first error that made the run fail, if any.
+    pub error: Option<Error>,
+}
+
+impl RunSummary {
+    pub fn is_success(&self) -> bool {
+        self.error.is_none()
+    }
+
+    pub fn count(&self, status: ScriptStatus) -> usize {
+        self.scripts.iter().filter(|s| s.status == status).count()
+    }
+}
+
+impl Display for RunSummary {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        let headers = ["SCRIPT", "STATUS", "EXIT", "DURATION", "ATTEMPTS"];
+        let rows = self
+            .scripts
+            .iter()
+            .map(|s| {
+                [
+                    s.name.clone(),
+                    s.status.to_string(),
+                    s.exit_code.map(|c| c.to_string()).unwrap_or_default(),
+                    s.duration.map(|d| format!("{:.2?}", d)).unwrap_or_default(),
+                    s.attempts.to_string(),
+                ]
+            })
+            .collect::<Vec<_>>();
+
+        let mut widths = headers.map(str::len);
+        for row in &rows {
+            for (width, cell) in widths.iter_mut().zip(row) {
+                *width = (*width).max(cell.chars().count());
+            }
+        }
+
+        let headers = headers.map(str::to_string);
+        for row in std::iter::once(&headers).chain(&rows) {
+            let line = row
+                .iter()
+                .zip(widths)
+                .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+                .collect::<Vec<_>>()
+                .join("  ");
+            writeln!(f, "{}", line.trim_end())?;
+        }
+
+        write!(
+            f,
+            "{} succeeded, {} up to date, {} cached, {} resumed, {} failed, {} skipped, {} timed out, {} cancelled in {:.2?}",
+            self.count(ScriptStatus::Succeeded),
+            self.count(ScriptStatus::UpToDate),
+            self.count(ScriptStatus::Cached),
+            self.count(ScriptStatus::Resumed),
+            self.count(ScriptStatus::Failed),
+            self.count(ScriptStatus::Skipped),
+            self.count(ScriptStatus::TimedOut),
+            self.count(ScriptStatus::Cancelled),
+            self.duration
+        )
+    }
+}
@@ -0,0 +1,78 @@
+//! Fixtures shared by the unit tests.
+
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use config::{Config, FileFormat};
+
+use crate::script::Script;
+
+/// An enabled inline script that runs `cmd`.
+pub fn cmd_script(name: &str, cmd: &str, dependencies: &[&str]) -> Script {
+    Script::new(
+        name,
+        Some(cmd.to_string()),
+        None,
+        None,
+        vec![],
+        dependencies.iter().map(|d| d.to_string()).collect(),
+        true,
+    )
+    .unwrap()
+}
+
+/// An enabled inline script that prints its own name.
+pub fn script(name: &str, dependencies: &[&str]) -> Script {
+    cmd_script(name, &format!("echo {}", name), dependencies)
+}
+
+/// `script` with `enabled = false`.
+pub fn disabled(mut script: Script) -> Script {
+    script.enabled = false;
+    script
+}
+
+/// A config read from the TOML in `toml`.
+pub fn config(toml: &str) -> Config {
+    Config::builder()
+        .add_source(config::File::from_str(toml, FileFormat::Toml))
+        .build()
+        .unwrap()
+}
+
+/// An empty directory in the system's temp dir, removed with its contents
+/// when dropped.
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    pub fn new(name: &str) -> TempDir {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let count = COUNT.fetch_add(1, Ordering::Relaxed);
+        let path =
+            std::env::temp_dir().join(format!("rdo-{}-{}-{}", name, std::process::id(), count));
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).unwrap();
+        TempDir(path)
+    }
+}
+
+impl Deref for TempDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl AsRef<Path> for TempDir {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
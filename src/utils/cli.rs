@@ -1,7 +1,18 @@
-use clap::{Parser, Subcommand};
+use std::collections::HashMap;
+use std::io::IsTerminal;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use clap::{Args, Parser, Subcommand, ValueEnum};
 use tokio::sync::mpsc::Receiver;
 use tokio::sync::watch::Sender;
 
+use crate::event::{Event, TimedEvent};
+use crate::executor::{FailurePolicy, Selection, Targets};
+use crate::graph::GraphFormat;
+use crate::junit::JunitReport;
+use crate::output::LogFiles;
+use crate::script::kill_running_scripts;
 use crate::utils::error::Error;
 
 #[derive(Parser)]
@@ -19,25 +30,192 @@ pub enum Commands {
         about = "Run the given script(s) and all of their dependencies",
         long_about = "Run the given script(s). If no scripts are given, all scripts will be run."
     )]
-    Run {
+    Run(RunArgs),
+
+    #[command(about = "List all scripts")]
+    List {
+        #[arg(value_name = "config", long)]
+        config: Option<String>,
+        #[arg(
+            value_name = "script",
+            long,
+            help = "Only list the scripts that depend on the given script(s)"
+        )]
+        dependents: Option<String>,
+    },
+
+    #[command(
+        about = "Explain why a script is run as part of another",
+        long_about = "Print every dependency path from the target script down to the given script."
+    )]
+    Why {
+        #[arg(value_name = "target")]
+        target: String,
+        #[arg(value_name = "script")]
+        script: String,
+        #[arg(value_name = "config", long)]
+        config: Option<String>,
+    },
+
+    #[command(
+        about = "Print the dependency graph",
+        long_about = "Print the dependency graph of the given script(s), or of all scripts if none are given."
+    )]
+    Graph {
         #[arg(value_name = "script", long, num_args =..)]
         scripts: Option<String>,
         #[arg(value_name = "config", long)]
         config: Option<String>,
+        #[arg(value_name = "format", long, value_enum, default_value_t)]
+        format: GraphFormat,
     },
 
-    #[command(about = "List all scripts")]
-    List {
+    #[command(about = "Manage the result cache")]
+    Cache {
+        #[command(subcommand)]
+        command: CacheCommands,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum CacheCommands {
+    #[command(about = "Remove every cached result")]
+    Clean {
         #[arg(value_name = "config", long)]
         config: Option<String>,
     },
 }
 
+/// How `rdo run` presents the run on stdout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum OutputFormat {
+    /// Script output prefixed with the script name, followed by a summary.
+    #[default]
+    Human,
+    /// One JSON event per line.
+    Json,
+}
+
+#[derive(Args, Default)]
+pub struct RunArgs {
+    #[arg(value_name = "script", long, num_args =..)]
+    pub scripts: Option<String>,
+    #[arg(
+        value_name = "script",
+        long,
+        conflicts_with = "scripts",
+        help = "Run the given script(s) and everything that depends on them"
+    )]
+    pub downstream: Option<String>,
+    #[arg(
+        long,
+        requires = "scripts",
+        help = "Run only the given script(s), without their dependencies"
+    )]
+    pub only: bool,
+    #[arg(
+        value_name = "script",
+        long,
+        help = "Start from the given script: run it and only those scripts that depend on it"
+    )]
+    pub from: Option<String>,
+    #[arg(
+        value_name = "script",
+        long,
+        help = "Leave out the given script(s); scripts that depend on them still run"
+    )]
+    pub skip: Option<String>,
+    #[arg(value_name = "config", long)]
+    pub config: Option<String>,
+    #[arg(
+        value_name = "jobs",
+        short,
+        long,
+        help = "Maximum number of scripts to run at the same time"
+    )]
+    pub jobs: Option<usize>,
+    #[arg(
+        value_name = "policy",
+        long,
+        value_enum,
+        help = "What to do when a script fails, unless the script sets its own on_failure"
+    )]
+    pub on_failure: Option<FailurePolicy>,
+    #[arg(
+        value_name = "duration",
+        long,
+        value_parser = humantime::parse_duration,
+        help = "Cancel the run if it takes longer than this, e.g. 30m"
+    )]
+    pub timeout: Option<Duration>,
+    #[arg(
+        value_name = "dir",
+        long,
+        help = "Write each script's output to <dir>/<run-id>/<script>.log instead of the configured directory"
+    )]
+    pub log_dir: Option<PathBuf>,
+    #[arg(long, conflicts_with = "log_dir", help = "Do not write log files")]
+    pub no_logs: bool,
+    #[arg(
+        value_name = "format",
+        long,
+        value_enum,
+        default_value_t,
+        help = "How to present the run on stdout"
+    )]
+    pub output: OutputFormat,
+    #[arg(
+        value_name = "path",
+        long,
+        help = "Write a JUnit XML report with one test case per script"
+    )]
+    pub junit: Option<PathBuf>,
+    #[arg(
+        long,
+        help = "Resume the last run: skip scripts that succeeded in it, unless their dependencies rerun"
+    )]
+    pub resume: bool,
+    #[arg(
+        long,
+        help = "Print the stages and commands that would run, without running anything"
+    )]
+    pub dry_run: bool,
+    #[arg(long, help = "Run every script instead of restoring cached results")]
+    pub no_cache: bool,
+    #[arg(long, help = "Do not colour script output")]
+    pub no_color: bool,
+}
+
+impl RunArgs {
+    pub fn selection(&self) -> Selection {
+        let targets = match (&self.scripts, &self.downstream) {
+            (_, Some(names)) => Targets::Downstream(split_names(names)),
+            (Some(names), None) => Targets::Scripts(split_names(names)),
+            (None, None) => Targets::All,
+        };
+
+        Selection {
+            targets,
+            only: self.only,
+            from: self.from.clone(),
+            skip: self.skip.as_deref().map(split_names).unwrap_or_default(),
+        }
+    }
+}
+
+/// Splits a comma-separated list of script names.
+pub fn split_names(names: &str) -> Vec<String> {
+    names.split(',').map(|s| s.to_string()).collect()
+}
+
 pub fn read_stdin(stdin_tx: Sender<String>) -> Result<(), Error> {
     let mut buffer = String::new();
     let stdin = std::io::stdin();
     loop {
-        stdin.read_line(&mut buffer)?;
+        if stdin.read_line(&mut buffer)? == 0 {
+            break;
+        }
+
         let result = stdin_tx.send(buffer.clone());
         match result {
             Ok(_) => (),
@@ -50,14 +228,64 @@ pub fn read_stdin(stdin_tx: Sender<String>) -> Result<(), Error> {
     Err(Error::StdinClosed)
 }
 
-pub async fn handle_signals() {
+/// Cancels the run on the first SIGINT. On the second, kills the scripts that
+/// are still running and exits.
+pub async fn handle_signals(cancel_tx: Sender<bool>) {
     tokio::signal::ctrl_c().await.unwrap();
-    info!("Received SIGINT, exiting");
-    std::process::exit(0);
+    info!("Received SIGINT, cancelling run (press Ctrl-C again to exit immediately)");
+    cancel_tx.send_replace(true);
+
+    tokio::signal::ctrl_c().await.unwrap();
+    info!("Received SIGINT, killing running scripts and exiting");
+    kill_running_scripts();
+    std::process::exit(1);
 }
 
-pub async fn handle_output(mut output_rx: Receiver<String>) {
-    while let Some(line) = output_rx.recv().await {
-        println!("{}", line);
+/// ANSI colours given to scripts in the order they first write output.
+const SCRIPT_COLORS: [&str; 10] = ["36", "32", "33", "34", "35", "96", "92", "93", "94", "95"];
+
+/// Prints events as they arrive in the given `format` and, if given, copies
+/// script output to `log_files` and records events in `junit`, which is
+/// returned once the run is over.
+pub async fn handle_output(
+    mut output_rx: Receiver<TimedEvent>,
+    format: OutputFormat,
+    color: bool,
+    mut log_files: Option<LogFiles>,
+    mut junit: Option<JunitReport>,
+) -> Option<JunitReport> {
+    let mut script_colors = HashMap::new();
+    while let Some(event) = output_rx.recv().await {
+        if let Some(junit) = &mut junit {
+            junit.record(&event.event);
+        }
+        if format == OutputFormat::Json {
+            println!("{}", event.to_json());
+        }
+        let Event::Output(output) = event.event else {
+            continue;
+        };
+
+        if let Some(log_files) = &mut log_files {
+            if let Err(e) = log_files.write(&output) {
+                error!("Failed to write log for {}: {}", output.script, e);
+            }
+        }
+
+        if format == OutputFormat::Human {
+            let script_color = color.then(|| {
+                let next = script_colors.len() % SCRIPT_COLORS.len();
+                *script_colors
+                    .entry(output.script.clone())
+                    .or_insert(SCRIPT_COLORS[next])
+            });
+            println!("{}", output.render(script_color));
+        }
     }
+    junit
+}
+
+/// Whether output should be coloured, given the `--no-color` flag.
+pub fn use_color(no_color: bool) -> bool {
+    !no_color && std::env::var_os("NO_COLOR").is_none() && std::io::stdout().is_terminal()
 }
@@ -1,4 +1,8 @@
-use config::Config;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use config::{Config, ConfigError};
+use serde::Deserialize;
 
 use crate::utils::error::Error;
 
@@ -10,21 +14,29 @@ pub enum ConfigType {
 }
 
 const CONFIG_DIR: &str = "config";
+/// Directory next to the config file where rdo keeps run state and caches.
+const STATE_DIR: &str = ".rdo";
+/// Extensions the config crate recognises, stripped from the config name.
+const CONFIG_EXTENSIONS: [&str; 7] = ["toml", "json", "json5", "yaml", "yml", "ini", "ron"];
 
-impl ToString for ConfigType {
-    fn to_string(&self) -> String {
+impl std::fmt::Display for ConfigType {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match self {
-            ConfigType::Production => "config".to_string(),
-            ConfigType::Test => "config.test".to_string(),
-            ConfigType::Default => "config.default".to_string(),
+            ConfigType::Production => write!(f, "config"),
+            ConfigType::Test => write!(f, "config.test"),
+            ConfigType::Default => write!(f, "config.default"),
         }
     }
 }
 
 pub fn get_config(config_type: ConfigType) -> Result<Config, Error> {
-    let path = format!("{}/{}", CONFIG_DIR, config_type.to_string());
+    let path = format!("{}/{}", CONFIG_DIR, config_type);
+    get_config_from_file(&path)
+}
+
+pub fn get_config_from_file(path: &str) -> Result<Config, Error> {
     let config = Config::builder()
-        .add_source(config::File::with_name(&path))
+        .add_source(config::File::with_name(path))
         .build();
 
     match config {
@@ -33,20 +45,99 @@ pub fn get_config(config_type: ConfigType) -> Result<Config, Error> {
     }
 }
 
-pub fn get_config_from_file(path: &str) -> Result<Config, Error> {
-    let config = Config::builder()
-        .add_source(config::File::with_name(path))
-        .build();
+/// The directory containing the config file at `config_path`, which relative
+/// paths in the config are resolved against.
+pub fn get_config_dir(config_path: &Path) -> Result<PathBuf, Error> {
+    match config_path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => Ok(parent.canonicalize()?),
+        _ => Ok(std::env::current_dir()?),
+    }
+}
 
-    match config {
-        Ok(config) => Ok(config),
+/// The directory holding rdo's state, such as the outcome of the last run,
+/// for configs in `config_dir`.
+pub fn get_state_dir(config_dir: &Path) -> PathBuf {
+    config_dir.join(STATE_DIR)
+}
+
+/// The name of the config file without its extension, e.g. `config.test`.
+pub fn get_config_name(config_path: &Path) -> String {
+    let file_name = config_path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    match file_name.rsplit_once('.') {
+        Some((name, extension)) if CONFIG_EXTENSIONS.contains(&extension) => name.to_string(),
+        _ => file_name,
+    }
+}
+
+/// Loads the config at `config_path`, or the production config if none is
+/// given. Returns the config together with the path it was loaded from.
+pub fn get_config_or_default(config_path: Option<String>) -> Result<(Config, PathBuf), Error> {
+    let path = config_path.unwrap_or_else(|| format!("{}/{}", CONFIG_DIR, ConfigType::Production));
+    let config = get_config_from_file(&path)?;
+    Ok((config, PathBuf::from(path)))
+}
+
+/// Returns `None` if `key` is not set, and an error if it is set to an invalid value.
+pub fn get_optional<'de, T: Deserialize<'de>>(
+    config: &Config,
+    key: &str,
+) -> Result<Option<T>, Error> {
+    match config.get::<T>(key) {
+        Ok(value) => Ok(Some(value)),
+        Err(ConfigError::NotFound(_)) => Ok(None),
         Err(err) => Err(Error::Config(err)),
     }
 }
 
-pub fn get_config_or_default(config_path: Option<String>) -> Result<Config, Error> {
-    match config_path {
-        Some(path) => get_config_from_file(&path),
-        None => get_config(ConfigType::Production),
+/// Reads a human-readable duration such as `"500ms"`, `"30s"` or `"5m"`.
+pub fn get_optional_duration(config: &Config, key: &str) -> Result<Option<Duration>, Error> {
+    match get_optional::<String>(config, key)? {
+        Some(value) => humantime::parse_duration(&value).map(Some).map_err(|err| {
+            Error::Config(ConfigError::Message(format!(
+                "invalid duration for {}: {}",
+                key, err
+            )))
+        }),
+        None => Ok(None),
     }
 }
+
+/// Reads a size in bytes such as `512`, `"500KB"`, `"1.5GB"` or `"2GiB"`.
+pub fn get_optional_size(config: &Config, key: &str) -> Result<Option<u64>, Error> {
+    let value = match get_optional::<String>(config, key)? {
+        Some(value) => value,
+        None => return Ok(None),
+    };
+
+    parse_size(&value).map(Some).ok_or_else(|| {
+        Error::Config(ConfigError::Message(format!(
+            "invalid size for {}: {}",
+            key, value
+        )))
+    })
+}
+
+fn parse_size(value: &str) -> Option<u64> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let number = number.parse::<f64>().ok()?;
+    let multiplier: u64 = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "kb" => 1000,
+        "mb" => 1000u64.pow(2),
+        "gb" => 1000u64.pow(3),
+        "tb" => 1000u64.pow(4),
+        "kib" => 1 << 10,
+        "mib" => 1 << 20,
+        "gib" => 1 << 30,
+        "tib" => 1 << 40,
+        _ => return None,
+    };
+    Some((number * multiplier as f64) as u64)
+}
@@ -4,7 +4,22 @@ pub enum Error {
     Parse(std::num::ParseIntError),
     Config(config::ConfigError),
     ScriptDependencyNotRun(String, String),
+    DependencyCycle(Vec<String>),
+    ScriptDisabled(String),
+    ScriptDependencyDisabled(String, String),
     ScriptNotFound(String),
+    InvalidScript(String, String),
+    ScriptFileNotFound(String, String),
+    ScriptNotExecutable(String, String),
+    DuplicateScript(String),
+    MissingDependency(String, String),
+    NotADependency(String, String),
+    ScriptNotSelected(String),
+    ScriptFailed(String, Option<i32>, Option<i32>),
+    ScriptTimedOut(String, std::time::Duration),
+    ScriptCancelled(String),
+    RunTimedOut(std::time::Duration),
+    RunCancelled,
     Unspecified(String),
     StdinClosed,
     StdoutClosed,
@@ -52,7 +67,53 @@ impl std::fmt::Display for Error {
             Error::ScriptDependencyNotRun(script, dep) => {
                 write!(f, "Dependency of {} not run: {}", script, dep)
             }
+            Error::DependencyCycle(cycle) => {
+                write!(f, "dependency cycle detected: {}", cycle.join(" -> "))
+            }
+            Error::ScriptDisabled(script) => write!(f, "script {} is disabled", script),
+            Error::ScriptDependencyDisabled(script, dep) => {
+                write!(f, "script {} depends on disabled script {}", script, dep)
+            }
             Error::ScriptNotFound(script) => write!(f, "script not found: {}", script),
+            Error::InvalidScript(script, reason) => write!(f, "script {}: {}", script, reason),
+            Error::ScriptFileNotFound(script, path) => {
+                write!(f, "script {}: {} is not a file", script, path)
+            }
+            Error::ScriptNotExecutable(script, path) => {
+                write!(f, "script {}: {} is not executable", script, path)
+            }
+            Error::DuplicateScript(script) => write!(f, "duplicate script: {}", script),
+            Error::MissingDependency(script, dep) => {
+                write!(f, "script {} depends on unknown script {}", script, dep)
+            }
+            Error::NotADependency(script, dep) => {
+                write!(f, "script {} does not depend on {}", script, dep)
+            }
+            Error::ScriptNotSelected(script) => {
+                write!(f, "script {} is not part of the run", script)
+            }
+            Error::ScriptFailed(script, Some(code), _) => {
+                write!(f, "script {} failed with exit code {}", script, code)
+            }
+            Error::ScriptFailed(script, None, Some(signal)) => {
+                write!(f, "script {} was terminated by signal {}", script, signal)
+            }
+            Error::ScriptFailed(script, None, None) => write!(f, "script {} failed", script),
+            Error::ScriptTimedOut(script, timeout) => write!(
+                f,
+                "script {} timed out after {}",
+                script,
+                humantime::format_duration(*timeout)
+            ),
+            Error::ScriptCancelled(script) => write!(f, "script {} was cancelled", script),
+            Error::RunTimedOut(timeout) => {
+                write!(
+                    f,
+                    "run timed out after {}",
+                    humantime::format_duration(*timeout)
+                )
+            }
+            Error::RunCancelled => write!(f, "run was cancelled"),
             Error::LoggingSetupFailed => write!(f, "Failed setting up logger"),
             Error::StdinClosed => write!(f, "Stdin closed"),
             Error::StdoutClosed => write!(f, "Stdout closed"),
@@ -0,0 +1,24 @@
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::utils::error::Error;
+
+/// Returns the paths matching any of `patterns`, sorted and without duplicates.
+pub fn expand_globs(patterns: &[String]) -> Result<Vec<PathBuf>, Error> {
+    let mut paths = Vec::new();
+    for pattern in patterns {
+        let entries = glob::glob(pattern)
+            .map_err(|err| Error::Unspecified(format!("invalid glob {}: {}", pattern, err)))?;
+        for entry in entries {
+            paths.push(entry.map_err(|err| Error::Io(err.into()))?);
+        }
+    }
+
+    paths.sort();
+    paths.dedup();
+    Ok(paths)
+}
+
+pub fn modified(path: &Path) -> Result<SystemTime, Error> {
+    Ok(std::fs::metadata(path)?.modified()?)
+}
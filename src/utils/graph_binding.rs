@@ -1,16 +1,22 @@
-use std::collections::{HashMap, HashSet};
-use std::fmt::Debug;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt::{Debug, Display};
 use std::hash::Hash;
 
+use petgraph::algo::tarjan_scc;
 use petgraph::graph::DiGraph;
 use petgraph::prelude::NodeIndex;
 use petgraph::visit::{NodeFiltered, Topo, Walker};
+use petgraph::Direction;
 
 use crate::utils::error::Error;
 
 pub trait GraphLike<'a, K> {
     fn get_key(&'a self) -> &'a K;
     fn get_children_keys(&'a self) -> Vec<&'a K>;
+
+    fn is_enabled(&'a self) -> bool {
+        true
+    }
 }
 
 pub struct GraphBinding<'a, T, K>
@@ -25,27 +31,79 @@ where
 impl<'a, T, K> GraphBinding<'a, T, K>
 where
     T: GraphLike<'a, K> + Debug + 'a,
-    K: Eq + Hash + Debug,
+    K: Eq + Hash + Debug + Display,
 {
     pub fn new(nodes: Vec<&'a T>) -> Result<GraphBinding<'a, T, K>, Error> {
         let graph = DiGraph::new();
         let key_to_id = HashMap::new();
         let mut graph_binding = GraphBinding { graph, key_to_id };
         graph_binding.add_nodes(nodes)?;
+
+        if let Some(cycle) = graph_binding.find_cycle() {
+            return Err(Error::DependencyCycle(cycle));
+        }
         Ok(graph_binding)
     }
 
-    fn add_node(&mut self, node: &'a T) {
-        let id = self.graph.add_node(node);
-        let result = self.key_to_id.insert(node.get_key(), id);
-        if result.is_some() {
-            panic!("Duplicate key found: {:?}", node.get_key())
+    /// Returns the keys along one dependency cycle, starting and ending with
+    /// the same key, or `None` if the graph is acyclic.
+    fn find_cycle(&self) -> Option<Vec<String>> {
+        for component in tarjan_scc(&self.graph) {
+            let is_cycle =
+                component.len() > 1 || self.graph.contains_edge(component[0], component[0]);
+            if !is_cycle {
+                continue;
+            }
+
+            let start = *component
+                .iter()
+                .min_by_key(|&&id| self.key_of(id).to_string())?;
+            let members = component.iter().copied().collect::<HashSet<NodeIndex>>();
+
+            // Breadth-first search along dependency edges back to `start`.
+            let mut parents = HashMap::new();
+            let mut queue = VecDeque::from([start]);
+            while let Some(id) = queue.pop_front() {
+                for dep in self.graph.neighbors_directed(id, Direction::Incoming) {
+                    if dep == start {
+                        let mut path = vec![id];
+                        while let Some(&parent) = parents.get(&path[path.len() - 1]) {
+                            path.push(parent);
+                        }
+                        path.reverse();
+                        path.push(start);
+                        return Some(path.iter().map(|&id| self.key_of(id).to_string()).collect());
+                    }
+
+                    if members.contains(&dep) && !parents.contains_key(&dep) {
+                        parents.insert(dep, id);
+                        queue.push_back(dep);
+                    }
+                }
+            }
         }
+
+        None
+    }
+
+    fn key_of(&self, id: NodeIndex) -> &'a K {
+        let node: &'a T = self.graph[id];
+        node.get_key()
+    }
+
+    fn add_node(&mut self, node: &'a T) -> Result<(), Error> {
+        if self.key_to_id.contains_key(node.get_key()) {
+            return Err(Error::DuplicateScript(node.get_key().to_string()));
+        }
+
+        let id = self.graph.add_node(node);
+        self.key_to_id.insert(node.get_key(), id);
+        Ok(())
     }
 
     fn add_nodes(&mut self, nodes: Vec<&'a T>) -> Result<(), Error> {
         for &node in nodes.iter() {
-            self.add_node(node);
+            self.add_node(node)?;
         }
         self.add_child_edges(nodes)
     }
@@ -54,7 +112,9 @@ where
         for node in nodes {
             let node_id = self.find_node_id_by_key(node.get_key())?;
             for child_key in node.get_children_keys() {
-                let child_id = self.find_node_id_by_key(child_key)?;
+                let child_id = self.find_node_id_by_key(child_key).map_err(|_| {
+                    Error::MissingDependency(node.get_key().to_string(), child_key.to_string())
+                })?;
                 self.graph.add_edge(child_id, node_id, ());
             }
         }
@@ -66,10 +126,7 @@ where
         let result = self.key_to_id.get(key);
         match result {
             Some(id) => Ok(*id),
-            None => Err(Error::Unspecified(format!(
-                "Could not find node with key {:?}",
-                key
-            ))),
+            None => Err(Error::ScriptNotFound(key.to_string())),
         }
     }
 
@@ -89,7 +146,7 @@ where
 
             for child_key in node.get_children_keys() {
                 let child_id = self.find_node_id_by_key(child_key)?;
-                if !closure.insert(child_id) {
+                if closure.contains(&child_id) {
                     continue;
                 }
                 queue.push(
@@ -103,10 +160,38 @@ where
         Ok(closure)
     }
 
+    /// Returns `nodes` and every node that depends on them, directly or
+    /// transitively.
+    pub fn get_dependents(&self, nodes: Vec<&'a T>) -> Result<Vec<&'a T>, Error> {
+        let mut dependents = HashSet::new();
+        let mut queue = Vec::new();
+        for node in nodes {
+            queue.push(self.find_node_id_by_key(node.get_key())?);
+        }
+
+        while let Some(id) = queue.pop() {
+            if dependents.insert(id) {
+                queue.extend(self.graph.neighbors_directed(id, Direction::Outgoing));
+            }
+        }
+
+        Ok(self
+            .graph
+            .node_indices()
+            .filter(|id| dependents.contains(id))
+            .map(|id| self.graph[id])
+            .collect())
+    }
+
     pub fn get_all_nodes(&self) -> Vec<&'a T> {
         self.graph.node_weights().copied().collect()
     }
 
+    pub fn find_node_by_key(&self, key: &K) -> Result<&'a T, Error> {
+        let node_id = self.find_node_id_by_key(key)?;
+        Ok(self.graph[node_id])
+    }
+
     pub fn find_nodes_by_keys(&self, keys: Vec<K>) -> Result<Vec<&'a T>, Error> {
         let mut nodes = Vec::new();
         for key in keys {
@@ -117,12 +202,102 @@ where
         Ok(nodes)
     }
 
+    /// Returns `nodes` and everything they depend on, or every node if `nodes`
+    /// is `None`, along with the `(dependency, dependent)` edges between them.
+    #[allow(clippy::type_complexity)]
+    pub fn get_subgraph(
+        &self,
+        nodes: Option<Vec<&'a T>>,
+    ) -> Result<(Vec<&'a T>, Vec<(&'a T, &'a T)>), Error> {
+        let node_ids = match nodes {
+            Some(nodes) => self.get_transitive_closure(nodes)?,
+            None => self.graph.node_indices().collect(),
+        };
+
+        let nodes = self
+            .graph
+            .node_indices()
+            .filter(|id| node_ids.contains(id))
+            .map(|id| self.graph[id])
+            .collect();
+        let edges = self
+            .graph
+            .edge_indices()
+            .filter_map(|edge| self.graph.edge_endpoints(edge))
+            .filter(|(from, to)| node_ids.contains(from) && node_ids.contains(to))
+            .map(|(from, to)| (self.graph[from], self.graph[to]))
+            .collect();
+        Ok((nodes, edges))
+    }
+
+    /// Returns every dependency path from `from` down to `to`, each starting
+    /// with `from` and ending with `to`, sorted by their keys.
+    pub fn find_dependency_paths(&self, from: &K, to: &K) -> Result<Vec<Vec<&'a K>>, Error> {
+        let from_id = self.find_node_id_by_key(from)?;
+        let to_id = self.find_node_id_by_key(to)?;
+
+        // Only nodes that depend on `to` can lie on a path to it, so walk up
+        // from `to` once and keep the search below to those.
+        let mut leads_to = HashSet::new();
+        let mut queue = VecDeque::from([to_id]);
+        while let Some(id) = queue.pop_front() {
+            if leads_to.insert(id) {
+                queue.extend(self.graph.neighbors_directed(id, Direction::Outgoing));
+            }
+        }
+
+        let mut paths = Vec::new();
+        if !leads_to.contains(&from_id) {
+            return Ok(paths);
+        }
+
+        let mut stack = vec![vec![from_id]];
+        while let Some(path) = stack.pop() {
+            let id = path[path.len() - 1];
+            if id == to_id {
+                paths.push(path.iter().map(|&id| self.key_of(id)).collect::<Vec<_>>());
+                continue;
+            }
+
+            for dep in self.graph.neighbors_directed(id, Direction::Incoming) {
+                if !leads_to.contains(&dep) {
+                    continue;
+                }
+                let mut path = path.clone();
+                path.push(dep);
+                stack.push(path);
+            }
+        }
+
+        paths.sort_by_cached_key(|path| path.iter().map(|key| key.to_string()).collect::<Vec<_>>());
+        Ok(paths)
+    }
+
+    /// Sorts `nodes` and everything they depend on so that each node comes
+    /// after its dependencies.
     pub fn topological_sort(&'a self, nodes: Vec<&'a T>) -> impl Iterator<Item = &'a T> + 'a {
         let node_ids = self.get_transitive_closure(nodes).unwrap();
-        let node_filter_fn = |id: NodeIndex| node_ids.contains(&id);
+        self.sort_node_ids(node_ids)
+    }
 
+    /// Sorts exactly the given `nodes` so that each node comes after those of
+    /// its dependencies that are also in `nodes`.
+    pub fn sort(&'a self, nodes: Vec<&'a T>) -> Result<impl Iterator<Item = &'a T> + 'a, Error> {
+        let node_ids = nodes
+            .iter()
+            .map(|node| self.find_node_id_by_key(node.get_key()))
+            .collect::<Result<HashSet<_>, _>>()?;
+        Ok(self.sort_node_ids(node_ids))
+    }
+
+    fn sort_node_ids(&'a self, node_ids: HashSet<NodeIndex>) -> impl Iterator<Item = &'a T> + 'a {
+        let node_filter_fn = move |id: NodeIndex| node_ids.contains(&id);
+
+        // Walk the filtered graph, so that dependencies outside `node_ids` do
+        // not hold back the nodes that depend on them.
         let filtered = NodeFiltered::from_fn(&self.graph, node_filter_fn);
-        Topo::new(&filtered).iter(&self.graph).map(|id| {
+        let sorted = Topo::new(&filtered).iter(&filtered).collect::<Vec<_>>();
+        sorted.into_iter().map(|id| {
             *self
                 .graph
                 .node_weight(id)
@@ -130,3 +305,204 @@ where
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug)]
+    struct Node {
+        key: String,
+        children: Vec<String>,
+    }
+
+    impl<'a> GraphLike<'a, String> for Node {
+        fn get_key(&'a self) -> &'a String {
+            &self.key
+        }
+
+        fn get_children_keys(&'a self) -> Vec<&'a String> {
+            self.children.iter().collect()
+        }
+    }
+
+    fn node(key: &str, children: Vec<&str>) -> Node {
+        Node {
+            key: key.to_string(),
+            children: children.iter().map(|c| c.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn test_topological_sort_includes_transitive_dependencies() {
+        let nodes = [
+            node("c", vec!["b"]),
+            node("b", vec!["a"]),
+            node("a", vec![]),
+            node("unrelated", vec![]),
+        ];
+        let graph_binding = GraphBinding::new(nodes.iter().collect()).unwrap();
+
+        let keys = graph_binding
+            .topological_sort(vec![&nodes[0]])
+            .map(|n| n.key.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(keys, ["a", "b", "c"]);
+    }
+
+    #[test]
+    fn test_cycle_is_reported_in_dependency_order() {
+        let nodes = [
+            node("a", vec!["b"]),
+            node("b", vec!["c"]),
+            node("c", vec!["a"]),
+            node("d", vec!["a"]),
+        ];
+
+        match GraphBinding::new(nodes.iter().collect()) {
+            Err(Error::DependencyCycle(cycle)) => assert_eq!(cycle, vec!["a", "b", "c", "a"]),
+            other => panic!("unexpected result: {:?}", other.err()),
+        }
+    }
+
+    #[test]
+    fn test_self_dependency_is_a_cycle() {
+        let nodes = [node("a", vec!["a"])];
+
+        match GraphBinding::new(nodes.iter().collect()) {
+            Err(Error::DependencyCycle(cycle)) => assert_eq!(cycle, vec!["a", "a"]),
+            other => panic!("unexpected result: {:?}", other.err()),
+        }
+    }
+
+    #[test]
+    fn test_acyclic_graph_is_accepted() {
+        let nodes = [
+            node("a", vec!["b", "c"]),
+            node("b", vec!["c"]),
+            node("c", vec![]),
+        ];
+        assert!(GraphBinding::new(nodes.iter().collect()).is_ok());
+    }
+
+    #[test]
+    fn test_subgraph_is_the_transitive_closure() {
+        let nodes = [
+            node("a", vec!["b"]),
+            node("b", vec!["c"]),
+            node("c", vec![]),
+            node("d", vec!["c"]),
+        ];
+        let graph_binding = GraphBinding::new(nodes.iter().collect()).unwrap();
+
+        let (nodes, edges) = graph_binding.get_subgraph(Some(vec![&nodes[1]])).unwrap();
+        let keys = nodes.iter().map(|n| n.key.as_str()).collect::<Vec<_>>();
+        let edges = edges
+            .iter()
+            .map(|(from, to)| (from.key.as_str(), to.key.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(keys, ["b", "c"]);
+        assert_eq!(edges, [("c", "b")]);
+
+        let (nodes, edges) = graph_binding.get_subgraph(None).unwrap();
+        assert_eq!(nodes.len(), 4);
+        assert_eq!(edges.len(), 3);
+    }
+
+    #[test]
+    fn test_find_dependency_paths() {
+        let nodes = [
+            node("deploy", vec!["build", "migrate"]),
+            node("build", vec!["config"]),
+            node("migrate", vec!["config"]),
+            node("config", vec![]),
+            node("lint", vec!["config"]),
+        ];
+        let graph_binding = GraphBinding::new(nodes.iter().collect()).unwrap();
+
+        let paths = graph_binding
+            .find_dependency_paths(&"deploy".to_string(), &"config".to_string())
+            .unwrap();
+        assert_eq!(
+            paths,
+            [
+                ["deploy", "build", "config"],
+                ["deploy", "migrate", "config"]
+            ]
+        );
+
+        let paths = graph_binding
+            .find_dependency_paths(&"deploy".to_string(), &"lint".to_string())
+            .unwrap();
+        assert!(paths.is_empty());
+    }
+
+    #[test]
+    fn test_find_dependency_paths_skips_branches_that_cannot_reach_the_script() {
+        // A ladder of diamonds has 2^40 paths from its top to its bottom, none
+        // of which lead to "lint".
+        let mut nodes = vec![node("lint", vec![]), node("step0", vec![])];
+        for i in 1..=40 {
+            let below = format!("step{}", i - 1);
+            nodes.push(Node {
+                key: format!("left{}", i),
+                children: vec![below.clone()],
+            });
+            nodes.push(Node {
+                key: format!("right{}", i),
+                children: vec![below],
+            });
+            nodes.push(Node {
+                key: format!("step{}", i),
+                children: vec![format!("left{}", i), format!("right{}", i)],
+            });
+        }
+        nodes.push(node("deploy", vec!["step40", "lint"]));
+        let graph_binding = GraphBinding::new(nodes.iter().collect()).unwrap();
+
+        let paths = graph_binding
+            .find_dependency_paths(&"deploy".to_string(), &"lint".to_string())
+            .unwrap();
+        assert_eq!(paths, [["deploy", "lint"]]);
+
+        let paths = graph_binding
+            .find_dependency_paths(&"step40".to_string(), &"lint".to_string())
+            .unwrap();
+        assert!(paths.is_empty());
+    }
+
+    #[test]
+    fn test_dependents_are_sorted_without_their_other_dependencies() {
+        let nodes = [
+            node("base", vec![]),
+            node("other", vec![]),
+            node("middle", vec!["base", "other"]),
+            node("top", vec!["middle"]),
+            node("unrelated", vec!["other"]),
+        ];
+        let graph_binding = GraphBinding::new(nodes.iter().collect()).unwrap();
+
+        let dependents = graph_binding.get_dependents(vec![&nodes[0]]).unwrap();
+        let sorted = graph_binding
+            .sort(dependents)
+            .unwrap()
+            .map(|n| n.key.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(sorted, ["base", "middle", "top"]);
+    }
+
+    #[test]
+    fn test_duplicate_and_missing_keys_are_errors() {
+        let duplicates = [node("a", vec![]), node("a", vec![])];
+        assert!(matches!(
+            GraphBinding::new(duplicates.iter().collect()),
+            Err(Error::DuplicateScript(key)) if key == "a"
+        ));
+
+        let missing = [node("a", vec!["b"])];
+        assert!(matches!(
+            GraphBinding::new(missing.iter().collect()),
+            Err(Error::MissingDependency(key, dep)) if key == "a" && dep == "b"
+        ));
+    }
+}
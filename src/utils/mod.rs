@@ -1,5 +1,6 @@
 pub mod cli;
 pub mod config;
 pub mod error;
+pub mod files;
 pub mod graph_binding;
 pub mod logger;